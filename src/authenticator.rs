@@ -6,15 +6,21 @@ use heapless_bytes::Bytes;
 use iso7816::{Data, Status};
 use trussed::types::KeyId;
 use trussed::types::Location;
+use trussed::types::Message;
+use trussed::types::ShortData;
+#[cfg(feature = "calculate-all")]
+use trussed::types::Signature;
 use trussed::{client, syscall, try_syscall, types::PathBuf};
 
-use crate::command::VerifyCode;
+use crate::calculate::FormattedCode;
+use crate::command::{ResyncCode, VerifyCode};
 use crate::credential::Credential;
 use crate::oath::Kind;
 use crate::{
-    command, ensure, oath,
-    state::{CommandState, State},
-    Command, ATTEMPT_COUNTER_DEFAULT_RETRIES, BACKEND_USER_PIN_ID,
+    command, ensure, hash_chain, oath, ocra, otpauth, recovery_codes,
+    state::{CommandState, EncryptionKeyType, State},
+    Command, ATTEMPT_COUNTER_DEFAULT_RETRIES, BACKEND_USER_PIN_ID, FAILURES_PER_POWER_CYCLE_MAX,
+    REVERSE_HOTP_RESYNC_WINDOW, REVERSE_TOTP_WINDOW,
 };
 
 /// The options for the authenticator app.
@@ -99,6 +105,8 @@ struct AnswerToSelect {
     // instead of '74 00', as with the tagged/Option derivation.
     // #[tlv(simple = "0x74")] // Tag::Challenge
     // challenge: Option<[u8; 8]>,
+    #[tlv(simple = "0x88")] // Tag::VerifyCodeCounter
+    verify_code_counter: [u8; 1],
 }
 
 #[derive(Clone, Copy, Encodable, Eq, PartialEq)]
@@ -108,8 +116,12 @@ struct PINAnswerToSelect {
 
     #[tlv(simple = "0x82")] // Tag::PINCounter
     attempt_counter: Option<[u8; 1]>,
+
+    #[tlv(simple = "0x88")] // Tag::VerifyCodeCounter
+    verify_code_counter: [u8; 1],
 }
 
+#[cfg(feature = "challenge-response-auth")]
 #[derive(Clone, Copy, Encodable, Eq, PartialEq)]
 struct ChallengingAnswerToSelect {
     #[tlv(simple = "0x79")] // Tag::Version
@@ -129,16 +141,31 @@ struct ChallengingAnswerToSelect {
     #[tlv(simple = "0x7b")] // Tag::Algorithm
     // algorithm: oath::Algorithm,
     algorithm: [u8; 1],
+
+    #[tlv(simple = "0x88")] // Tag::VerifyCodeCounter
+    verify_code_counter: [u8; 1],
+}
+
+/// Answer sent instead of the regular one, when the persistent state can't be read
+#[derive(Clone, Copy, Encodable, Eq, PartialEq)]
+struct CorruptedStateAnswerToSelect {
+    #[tlv(simple = "0x79")] // Tag::Version
+    version: OathVersion,
+
+    #[tlv(simple = "0x9d")] // Tag::StateCorrupted
+    state_corrupted: [u8; 1],
 }
 
 impl AnswerToSelect {
     /// The salt is stable and used in modified form as "device ID" in ykman.
     /// It gets rotated on device reset.
-    fn new(salt: [u8; 8]) -> Self {
+    /// The remaining Reverse HOTP verification attempts are reported in all the variants.
+    fn new(salt: [u8; 8], verify_code_counter: u8) -> Self {
         Self {
             version: Default::default(),
             salt,
             // challenge: None,
+            verify_code_counter: [verify_code_counter],
         }
     }
 
@@ -147,6 +174,7 @@ impl AnswerToSelect {
         PINAnswerToSelect {
             version: self.version,
             attempt_counter: c,
+            verify_code_counter: self.verify_code_counter,
         }
     }
 
@@ -161,6 +189,7 @@ impl AnswerToSelect {
             challenge,
             // algorithm: oath::Algorithm::Sha1  // TODO set proper algo
             algorithm: [0x01], // TODO set proper algo
+            verify_code_counter: self.verify_code_counter,
         }
     }
 }
@@ -170,6 +199,7 @@ where
     T: client::Client
         + client::HmacSha1
         + client::HmacSha256
+        + client::HmacSha512
         + client::Sha256
         + client::Chacha8Poly1305
         + trussed_auth::AuthClient,
@@ -196,10 +226,11 @@ where
         command: &iso7816::Command<C>,
         reply: &mut Data<R>,
     ) -> Result {
-        #[cfg(feature = "challenge-response-auth")]
-        let no_authorization_needed_cha_resp = self
-            .state
-            .with_persistent(&mut self.trussed, |_, state| !state.password_set());
+        // Only collect the chained APDUs here. The authorization state is left untouched,
+        // so it is still there for the final command assembled from them.
+        if !command.class().chain().last_or_only() {
+            return self.buffer_chained_command(command);
+        }
 
         // TODO: abstract out this idea to make it usable for all the PIV security indicators
 
@@ -224,16 +255,69 @@ where
         result
     }
 
+    fn buffer_chained_command<const C: usize>(&mut self, command: &iso7816::Command<C>) -> Result {
+        let class = command.class();
+        ensure(
+            class.secure_messaging().none(),
+            Status::SecureMessagingNotSupported,
+        )?;
+        ensure(class.channel() == Some(0), Status::ClassNotSupported)?;
+
+        // A chained APDU with a different header starts a new chain
+        if let Some(buffered) = &self.state.runtime.chained_command {
+            if !Self::is_chain_continuation(buffered, command) {
+                self.state.runtime.chained_command = None;
+            }
+        }
+
+        let buffered =
+            self.state.runtime.chained_command.get_or_insert_with(|| {
+                iso7816::Command::try_from(&[0x00, 0x00, 0x00, 0x00]).unwrap()
+            });
+        if buffered.extend_from_command(command).is_err() {
+            self.state.runtime.chained_command = None;
+            return Err(Status::WrongLength);
+        }
+        debug_now!(
+            "Buffered chained APDU, {} bytes in total",
+            buffered.data().len()
+        );
+        Ok(())
+    }
+
+    fn is_chain_continuation<const B: usize, const C: usize>(
+        buffered: &iso7816::Command<B>,
+        command: &iso7816::Command<C>,
+    ) -> bool {
+        let buffered_instruction: u8 = buffered.instruction().into();
+        let instruction: u8 = command.instruction().into();
+        (buffered_instruction, buffered.p1, buffered.p2) == (instruction, command.p1, command.p2)
+    }
+
     fn inner_respond<const C: usize, const R: usize>(
         &mut self,
         command: &iso7816::Command<C>,
         reply: &mut Data<R>,
+    ) -> Result {
+        // Finish the chain with its last APDU. Any other command interrupts and discards it.
+        if let Some(mut buffered) = self.state.runtime.chained_command.take() {
+            if Self::is_chain_continuation(&buffered, command) {
+                buffered
+                    .extend_from_command(command)
+                    .map_err(|_| Status::WrongLength)?;
+                return self.dispatch_command(&buffered, reply);
+            }
+            info_now!("Chained command interrupted, discarding");
+        }
+        self.dispatch_command(command, reply)
+    }
+
+    fn dispatch_command<const C: usize, const R: usize>(
+        &mut self,
+        command: &iso7816::Command<C>,
+        reply: &mut Data<R>,
     ) -> Result {
         let class = command.class();
-        ensure(
-            class.chain().last_or_only(),
-            Status::CommandChainingNotSupported,
-        )?;
         ensure(
             class.secure_messaging().none(),
             Status::SecureMessagingNotSupported,
@@ -244,6 +328,18 @@ where
         let command: Command = command.try_into()?;
         info_now!("{:?}", &command);
 
+        // The continuation is valid only right after the command it belongs to, so the PIN
+        // authorization of an interrupted listing can't be picked up later on
+        if !matches!(command, Command::SendRemaining) {
+            self.state.runtime.previously = None;
+        }
+
+        // Do not work on the corrupted persistent state. Only the reset is allowed then, and
+        // the SELECT reporting it, so the clients could get to the reset.
+        if !matches!(command, Command::Reset | Command::Select(_)) {
+            self.state.check_persistent(&mut self.trussed)?;
+        }
+
         if !self.state.runtime.client_authorized {
             match command {
                 Command::Select(_) => {}
@@ -252,6 +348,10 @@ where
                 Command::Reset => {}
                 // Always allow HOTP code verification
                 Command::VerifyCode(_) => {}
+                // Checks the PIN on its own, if it is set
+                Command::ResyncCode(_) => {}
+                // Reveals no secrets, same as HOTP code verification
+                Command::GetCounter(_) => {}
                 // Always allow to set PIN
                 Command::SetPin(_) => {}
                 // Always allow to verify PIN
@@ -260,17 +360,39 @@ where
                 Command::SendRemaining => {}
                 // No need to call verify on that, since it requires original PIN anyway
                 Command::ChangePin(_) => {}
+                // The access is checked per credential, as not all of them require PIN.
+                // Adding, removing and renaming needs the PIN once it is set, or touch otherwise.
+                Command::Register(_) => {}
+                Command::RegisterUri(_) => {}
+                Command::Calculate(_) => {}
+                Command::CalculateOcra(_) => {}
+                Command::CalculateHashChain(_) => {}
+                Command::UseRecoveryCode(_) => {}
+                #[cfg(feature = "calculate-all")]
+                Command::CalculateAll(_) => {}
+                Command::ListCredentials(_) => {}
+                Command::Delete(_) => {}
+                Command::Rename(_) => {}
                 _ => return Err(Status::ConditionsOfUseNotSatisfied),
             }
         }
         match command {
             Command::Select(select) => self.select(select, reply),
-            Command::ListCredentials => self.list_credentials(reply, None),
-            Command::Register(register) => self.register(register),
+            Command::ListCredentials(list) => self.list_credentials(reply, list, None),
+            Command::Register(register) => self.register(register, reply),
+            Command::RegisterUri(register_uri) => self.register_uri(register_uri, reply),
             Command::Calculate(calculate) => self.calculate(calculate, reply),
+            Command::CalculateOcra(calculate_ocra) => self.calculate_ocra(calculate_ocra, reply),
+            Command::CalculateHashChain(calculate_hash_chain) => {
+                self.calculate_hash_chain(calculate_hash_chain, reply)
+            }
+            Command::UseRecoveryCode(use_recovery_code) => {
+                self.use_recovery_code(use_recovery_code, reply)
+            }
             #[cfg(feature = "calculate-all")]
             Command::CalculateAll(calculate_all) => self.calculate_all(calculate_all, reply),
             Command::Delete(delete) => self.delete(delete),
+            Command::Rename(rename) => self.rename(rename),
             Command::Reset => self.reset(),
             #[cfg(feature = "challenge-response-auth")]
             Command::Validate(validate) => self.validate(validate, reply),
@@ -279,6 +401,9 @@ where
             #[cfg(feature = "challenge-response-auth")]
             Command::ClearPassword => self.clear_password(),
             Command::VerifyCode(verify_code) => self.verify_code(verify_code, reply),
+            Command::ResyncCode(resync_code) => self.resync_code(resync_code),
+            Command::GetCounter(get_counter) => self.get_counter(get_counter, reply),
+            Command::GetCredential(get_credential) => self.get_credential(get_credential, reply),
 
             Command::VerifyPin(vpin) => self.verify_pin(vpin, reply),
             Command::SetPin(spin) => self.set_pin(spin, reply),
@@ -294,16 +419,44 @@ where
         _select: command::Select<'_>,
         reply: &mut Data<R>,
     ) -> Result {
+        self.state.runtime.chained_command = None;
         self.state.runtime.challenge = syscall!(self.trussed.random_bytes(8))
             .bytes
             .as_ref()
             .try_into()
             .unwrap();
 
-        let state = self
+        let state = match self
             .state
-            .with_persistent(&mut self.trussed, |_, state| state.clone());
-        let answer_to_select = AnswerToSelect::new(state.salt);
+            .with_persistent(&mut self.trussed, |_, state| state.clone())
+        {
+            Ok(state) => state,
+            Err(Status::UnspecifiedPersistentExecutionError) => {
+                let data: heapless::Vec<u8, 128> = CorruptedStateAnswerToSelect {
+                    version: Default::default(),
+                    state_corrupted: [0x01],
+                }
+                .to_heapless_vec()
+                .unwrap();
+                reply.extend_from_slice(&data).unwrap();
+                return Ok(());
+            }
+            Err(status) => return Err(status),
+        };
+        let answer_to_select = AnswerToSelect::new(state.salt, state.verify_code_retries());
+
+        // Signal to the client, that the VALIDATE call is needed
+        #[cfg(feature = "challenge-response-auth")]
+        {
+            if state.password_set() {
+                let data: heapless::Vec<u8, 128> = answer_to_select
+                    .with_challenge(self.state.runtime.challenge)
+                    .to_heapless_vec()
+                    .unwrap();
+                reply.extend_from_slice(&data).unwrap();
+                return Ok(());
+            }
+        }
 
         let data: heapless::Vec<u8, 128> = if self._extension_is_pin_set()? {
             answer_to_select
@@ -331,6 +484,35 @@ where
         Some(credential)
     }
 
+    /// Check if the credential of the given protection class can be used in this session.
+    /// The PIN-based ones require the PIN verification, while the device-bound ones are always
+    /// accessible, unless the challenge-response password is set.
+    fn check_access(&mut self, encryption_key_type: EncryptionKeyType) -> Result {
+        if self.state.runtime.client_authorized {
+            return Ok(());
+        }
+        #[cfg(feature = "challenge-response-auth")]
+        if self
+            .state
+            .with_persistent(&mut self.trussed, |_, state| state.password_set())?
+        {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        match encryption_key_type {
+            EncryptionKeyType::Hardware => Ok(()),
+            EncryptionKeyType::PinBased => Err(Status::ConditionsOfUseNotSatisfied),
+        }
+    }
+
+    /// Check if the credentials can be added, removed or renamed in this session. Once the PIN
+    /// is set, this requires its verification, regardless of the credential's protection class.
+    fn check_modification_access(&mut self) -> Result {
+        if !self.state.runtime.client_authorized && self._extension_is_pin_set()? {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        self.check_access(EncryptionKeyType::Hardware)
+    }
+
     fn reset(&mut self) -> Result {
         self.user_present()?;
 
@@ -351,10 +533,18 @@ where
     }
 
     fn delete(&mut self, delete: command::Delete<'_>) -> Result {
+        self.check_modification_access()?;
+        debug_now!("{:?}", delete);
+        // Without the PIN verification, the user has to confirm the removal
         if !self.state.runtime.client_authorized {
-            return Err(Status::ConditionsOfUseNotSatisfied);
+            self.user_present()?;
         }
-        debug_now!("{:?}", delete);
+        self.delete_credential(delete.label)
+    }
+
+    /// Remove the credential and its secret, if it exists. Fails, if the credential is not
+    /// accessible in this session.
+    fn delete_credential(&mut self, label: &[u8]) -> Result {
         // It seems tooling first lists all credentials, so the case of
         // delete being called on a non-existing label hardly occurs.
 
@@ -366,35 +556,124 @@ where
         // APDU: 00 02 00 00 08 71 06 79 75 62 69 63 6F
         // SW: 90 00
 
-        let label = &delete.label;
-        if let Some(credential) = self.load_credential(label) {
-            let _deletion_result_secret = try_syscall!(self.trussed.delete(credential.secret));
+        let filename = self.filename_for_label(label);
+        let credential = match self.load_credential(label) {
+            Some(credential) => credential,
+            // A PIN-based credential can't be loaded before the PIN verification,
+            // and should not be removed then either
+            None if !self.state.runtime.client_authorized
+                && try_syscall!(self
+                    .trussed
+                    .read_file(self.options.location, filename.clone()))
+                .is_ok() =>
+            {
+                return Err(Status::ConditionsOfUseNotSatisfied)
+            }
+            None => return Ok(()),
+        };
+        self.check_access(credential.encryption_key_type)?;
+
+        if let Some(secret) = credential.secret {
+            let _deletion_result_secret = try_syscall!(self.trussed.delete(secret));
             debug_now!(
                 "Deleted secret {:?}, result: {:?}",
-                credential.secret,
+                secret,
                 _deletion_result_secret
             );
-
-            let _filename = self.filename_for_label(label);
-            let _deletion_result =
-                try_syscall!(self.trussed.remove_file(self.options.location, _filename));
-            debug_now!(
-                "Delete credential with filename {}, result: {:?}",
-                &self.filename_for_label(label),
-                _deletion_result
-            );
         }
+
+        let _deletion_result = try_syscall!(self
+            .trussed
+            .remove_file(self.options.location, filename.clone()));
+        debug_now!(
+            "Delete credential with filename {}, result: {:?}",
+            &filename,
+            _deletion_result
+        );
         Ok(())
     }
 
+    fn rename(&mut self, rename: command::Rename<'_>) -> Result {
+        self.check_modification_access()?;
+        debug_now!("{:?}", rename);
+        if !self.state.runtime.client_authorized {
+            self.user_present()?;
+        }
+
+        let mut credential = self.load_credential(rename.label).ok_or(Status::NotFound)?;
+        self.check_access(credential.encryption_key_type)?;
+
+        // Do not overwrite another credential
+        let new_filename = self.filename_for_label(rename.new_label);
+        if try_syscall!(self
+            .trussed
+            .read_file(self.options.location, new_filename.clone()))
+        .is_ok()
+        {
+            return Err(Status::IncorrectDataParameter);
+        }
+
+        credential.label =
+            ShortData::from_slice(rename.new_label).map_err(|_| Status::IncorrectDataParameter)?;
+
+        // 1. Store the credential under the new label. The secret's key handle stays the same.
+        // 2. Remove the old file.
+        // On failure of either, remove the new file, so the credential stays under the old label
+        // only, and would not be accessible under both.
+        let old_filename = self.filename_for_label(rename.label);
+        let res = self
+            .state
+            .try_write_file(
+                &mut self.trussed,
+                new_filename.clone(),
+                &credential,
+                credential.encryption_key_type,
+            )
+            .and_then(|_| {
+                try_syscall!(self
+                    .trussed
+                    .remove_file(self.options.location, old_filename))
+                .map(drop)
+                .map_err(|_| Status::NotEnoughMemory)
+            });
+        if res.is_err() {
+            try_syscall!(self
+                .trussed
+                .remove_file(self.options.location, new_filename))
+            .ok();
+        }
+        res
+    }
+
     fn try_to_serialize_credential_for_list<const R: usize>(
         credential: &Credential,
+        details: bool,
         reply: &mut Data<R>,
     ) -> core::result::Result<(), u8> {
         reply.push(0x72)?;
         reply.push((credential.label.len() + 1) as u8)?;
         reply.push(oath::combine(credential.kind, credential.algorithm))?;
         reply.extend_from_slice(&credential.label).map_err(|_| 0)?;
+        // Only on request, as the YKOATH clients expect the name list entries alone
+        if details {
+            for (tag, value) in [
+                (oath::Tag::Issuer, &credential.issuer),
+                (oath::Tag::Account, &credential.account),
+                (oath::Tag::DisplayName, &credential.display_name),
+            ] {
+                if let Some(value) = value {
+                    Self::push_tlv(reply, tag, value).map_err(|_| 0)?;
+                }
+            }
+            if credential.kind == oath::Kind::RecoveryCodes {
+                Self::push_tlv(
+                    reply,
+                    oath::Tag::RecoveryCodesRemaining,
+                    &[credential.recovery_codes_remaining() as u8],
+                )
+                .map_err(|_| 0)?;
+            }
+        }
         #[cfg(feature = "devel-ctaphid-bug")]
         if reply.len() > 3072 {
             // Finish early due to the usbd-ctaphid bug, which panics on bigger buffers than this
@@ -408,11 +687,18 @@ where
     fn list_credentials<const R: usize>(
         &mut self,
         reply: &mut Data<R>,
-        file_index: Option<usize>,
+        list: command::ListCredentials,
+        continuation: Option<(usize, bool)>,
     ) -> Result {
-        if !self.state.runtime.client_authorized && self.state.runtime.previously.is_none() {
-            return Err(Status::ConditionsOfUseNotSatisfied);
-        }
+        // The PIN-based credentials are listed only after the PIN verification,
+        // which is not repeated for the continuation
+        let (file_index, pin_authorized) = match continuation {
+            Some(continuation) => continuation,
+            None => {
+                self.check_access(EncryptionKeyType::Hardware)?;
+                (0, self.state.runtime.client_authorized)
+            }
+        };
         // info_now!("recv ListCredentials");
         // return Ok(Default::default());
         // 72 13 21
@@ -420,62 +706,34 @@ where
         // 72 07 21
         //          79 75 62 69  63 6F
         // 90 00
-        let file_index = file_index.unwrap_or(0);
-
-        let mut maybe_credential = {
-            // To avoid creating additional buffer for the unfit data
-            // we will rewind the state and restart from there accordingly
-            let first_file = try_syscall!(self.trussed.read_dir_files_first(
-                self.options.location,
-                Self::credential_directory(),
-                None
-            ))
-            .map_err(|_| iso7816::Status::KeyReferenceNotFound)?
-            .data;
-
-            // Rewind if needed, otherwise return first file's content
-            let file = {
-                if file_index > 0 {
-                    for _ in 0..file_index - 1 {
-                        try_syscall!(self.trussed.read_dir_files_next())
-                            .map_err(|_| iso7816::Status::KeyReferenceNotFound)?;
-                    }
-                    try_syscall!(self.trussed.read_dir_files_next())
-                        .map_err(|_| iso7816::Status::KeyReferenceNotFound)?
-                        .data
-                } else {
-                    first_file
-                }
-            };
 
-            let maybe_credential: Option<Credential> = match file {
-                None => None,
-                Some(c) => self.state.decrypt_content(&mut self.trussed, c).ok(),
-            };
-            maybe_credential
-        };
+        let mut maybe_file = self.read_credential_file_at_index(file_index)?;
 
         let mut file_index = file_index;
-        while let Some(credential) = maybe_credential {
-            // Try to serialize, abort if not succeeded
-            let current_reply_bytes_count = reply.len();
-            let res = Self::try_to_serialize_credential_for_list(&credential, reply);
-            if res.is_err() {
-                // Revert reply vector to the last good size, removing debris from the failed
-                // serialization
-                reply.truncate(current_reply_bytes_count);
-                return Err(Status::MoreAvailable(0xFF));
+        while let Some(file) = maybe_file {
+            if let Some(credential) = self.decrypt_credential(file, pin_authorized) {
+                // Try to serialize, abort if not succeeded
+                let current_reply_bytes_count = reply.len();
+                let res =
+                    Self::try_to_serialize_credential_for_list(&credential, list.details, reply);
+                if res.is_err() {
+                    // Revert reply vector to the last good size, removing debris from the failed
+                    // serialization
+                    reply.truncate(current_reply_bytes_count);
+                    return Err(Status::MoreAvailable(0xFF));
+                }
             }
 
             // keep track, in case we need continuation
             file_index += 1;
-            self.state.runtime.previously = Some(CommandState::ListCredentials(file_index));
+            self.state.runtime.previously = Some(CommandState::ListCredentials {
+                file_index,
+                pin_authorized,
+                details: list.details,
+            });
 
             // check if there's more
-            maybe_credential = match syscall!(self.trussed.read_dir_files_next()).data {
-                None => None,
-                Some(c) => self.state.decrypt_content(&mut self.trussed, c).ok(),
-            };
+            maybe_file = syscall!(self.trussed.read_dir_files_next()).data;
         }
 
         // ran to completion
@@ -484,74 +742,223 @@ where
         Ok(())
     }
 
-    fn send_remaining<const R: usize>(&mut self, reply: &mut Data<{ R }>) -> Result {
-        let file_index = if let Some(CommandState::ListCredentials(s_file_index)) =
-            self.state.runtime.previously
-        {
-            s_file_index
+    /// Reads the credential file with the given index in the credentials directory, and leaves
+    /// the directory iterator there, so the following ones can be read with `read_dir_files_next`.
+    fn read_credential_file_at_index(&mut self, file_index: usize) -> Result<Option<Message>> {
+        // To avoid creating additional buffer for the unfit data
+        // we will rewind the state and restart from there accordingly
+        let first_file = try_syscall!(self.trussed.read_dir_files_first(
+            self.options.location,
+            Self::credential_directory(),
+            None
+        ))
+        .map_err(|_| iso7816::Status::KeyReferenceNotFound)?
+        .data;
+
+        // Rewind if needed, otherwise return first file's content
+        if file_index > 0 {
+            for _ in 0..file_index - 1 {
+                try_syscall!(self.trussed.read_dir_files_next())
+                    .map_err(|_| iso7816::Status::KeyReferenceNotFound)?;
+            }
+            Ok(try_syscall!(self.trussed.read_dir_files_next())
+                .map_err(|_| iso7816::Status::KeyReferenceNotFound)?
+                .data)
         } else {
-            0
-        };
+            Ok(first_file)
+        }
+    }
 
-        match self.state.runtime.previously {
+    /// Decrypts the credential file read during the directory iteration. Returns None for
+    /// the credentials, which can't be decrypted with the available keys, or which are
+    /// PIN-based, while the PIN was not verified - these are skipped by the callers.
+    fn decrypt_credential(&mut self, file: Message, pin_authorized: bool) -> Option<Credential> {
+        let credential: Credential = self.state.decrypt_content(&mut self.trussed, file).ok()?;
+        match credential.encryption_key_type {
+            EncryptionKeyType::PinBased if !pin_authorized => None,
+            _ => Some(credential),
+        }
+    }
+
+    fn send_remaining<const R: usize>(&mut self, reply: &mut Data<{ R }>) -> Result {
+        match self.state.runtime.previously.clone() {
             None => Err(Status::ConditionsOfUseNotSatisfied),
-            Some(CommandState::ListCredentials(_)) => {
-                self.list_credentials(reply, Some(file_index))
-            }
+            Some(CommandState::ListCredentials {
+                file_index,
+                pin_authorized,
+                details,
+            }) => self.list_credentials(
+                reply,
+                command::ListCredentials { details },
+                Some((file_index, pin_authorized)),
+            ),
+            #[cfg(feature = "calculate-all")]
+            Some(CommandState::CalculateAll {
+                file_index,
+                pin_authorized,
+                challenge,
+                response_truncated,
+                unix_time,
+            }) => self.calculate_all_from_index(
+                reply,
+                challenge,
+                response_truncated,
+                unix_time,
+                file_index,
+                pin_authorized,
+            ),
         }
     }
 
-    fn register(&mut self, register: command::Register<'_>) -> Result {
+    fn register<const R: usize>(
+        &mut self,
+        register: command::Register<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
         self.user_present()?;
 
-        if !self.state.runtime.client_authorized {
-            return Err(Status::ConditionsOfUseNotSatisfied);
-        }
+        self.check_modification_access()?;
+        // Unless requested otherwise, keep the credentials behind the PIN once it is set,
+        // so these registered by the stock clients would not be usable without it
+        let encryption_key_type = match register.credential.encryption_key_type {
+            Some(encryption_key_type) => encryption_key_type,
+            None if self._extension_is_pin_set()? => EncryptionKeyType::PinBased,
+            None => EncryptionKeyType::Hardware,
+        };
+        self.check_access(encryption_key_type)?;
+        let register = command::Register {
+            credential: command::Credential {
+                encryption_key_type: Some(encryption_key_type),
+                ..register.credential
+            },
+            ..register
+        };
         // info_now!("recv {:?}", &register);
 
         // 0. ykman does not call delete before register, so we need to speculatively
         // delete the credential (the credential file would be replaced, but we need
-        // to delete the secret key).
-        self.delete(command::Delete {
-            label: register.credential.label,
-        })
-        .ok();
-
-        // 1. Store secret in Trussed
-        let raw_key = register.credential.secret;
-        let key_handle = try_syscall!(self
-            .trussed
-            .unsafe_inject_shared_key(raw_key, self.options.location))
-        .map_err(|_| Status::NotEnoughMemory)?
-        .key;
+        // to delete the secret key). Fails, if the current one is not accessible.
+        self.delete_credential(register.credential.label)?;
+
+        // 1. Store secret in Trussed. The S/KEY hash chain is calculated from the passphrase key
+        // kept in the credential instead, and the passphrase itself is never stored.
+        let hash_chain_key = match register.credential.hash_chain_seed {
+            Some(seed) => Some(
+                hash_chain::passphrase_key(
+                    register.credential.algorithm,
+                    seed,
+                    register.credential.secret,
+                )
+                .ok_or(Status::IncorrectDataParameter)?,
+            ),
+            None => None,
+        };
+        // The recovery codes come with no secret at all.
+        // The generated secret is as long as the HMAC output.
+        let random_key;
+        let raw_key = match register.credential.kind {
+            oath::Kind::HashChain | oath::Kind::RecoveryCodes => None,
+            _ if register.generate_secret => {
+                let length = match register.credential.algorithm {
+                    oath::Algorithm::Sha256 => 32,
+                    oath::Algorithm::Sha512 => 64,
+                    _ => 20,
+                };
+                random_key = syscall!(self.trussed.random_bytes(length)).bytes;
+                Some(&random_key[..])
+            }
+            _ => Some(register.credential.secret),
+        };
+        let key_handle = match raw_key {
+            Some(raw_key) => Some(
+                try_syscall!(self
+                    .trussed
+                    .unsafe_inject_shared_key(raw_key, self.options.location))
+                .map_err(|_| Status::NotEnoughMemory)?
+                .key,
+            ),
+            None => None,
+        };
         // info!("new key handle: {:?}", key_handle);
 
         // 2. Replace secret in credential with handle
-        let credential = Credential::try_from(&register.credential, key_handle)
+        let mut credential = Credential::try_from(&register.credential, key_handle)
             .map_err(|_| Status::NotEnoughMemory)?;
+        credential.hash_chain_key = hash_chain_key;
+
+        // 3. Prepare the response with the generated secret, which is not readable from the
+        // device afterwards. Done before storing, so the credential is never kept with its secret
+        // not returned to the host.
+        let reply_length = reply.len();
+        let response_res = match (register.generate_secret, raw_key) {
+            (true, Some(raw_key)) => Self::push_generated_secret(reply, &credential, raw_key),
+            _ => Ok(()),
+        };
 
-        // 3. Generate a filename for the credential
+        // 4. Generate a filename for the credential
         let filename = self.filename_for_label(&credential.label);
 
-        // 4. Serialize the credential (implicitly) and store it
-        let write_res = self
-            .state
-            .try_write_file(&mut self.trussed, filename, &credential);
+        // 5. Serialize the credential (implicitly) and store it
+        let write_res = response_res.and_then(|_| {
+            self.state.try_write_file(
+                &mut self.trussed,
+                filename,
+                &credential,
+                credential.encryption_key_type,
+            )
+        });
 
         if write_res.is_err() {
             // TODO reuse delete() call
-            // 1. Try to delete the key from Trussed, ignore errors
-            try_syscall!(self.trussed.delete(credential.secret)).ok();
-            // 2. Try to delete the empty file, ignore errors
+            // 1. Drop the prepared response
+            reply.truncate(reply_length);
+            // 2. Try to delete the key from Trussed, ignore errors
+            if let Some(secret) = credential.secret {
+                try_syscall!(self.trussed.delete(secret)).ok();
+            }
+            // 3. Try to delete the empty file, ignore errors
             let filename = self.filename_for_label(&credential.label);
             try_syscall!(self.trussed.remove_file(self.options.location, filename)).ok();
-            // 3. Return the original error
+            // 4. Return the original error
             write_res?
         }
 
         Ok(())
     }
 
+    /// Append the generated secret, in raw bytes and as the `otpauth://` URI
+    fn push_generated_secret<const R: usize>(
+        reply: &mut Data<R>,
+        credential: &Credential,
+        secret: &[u8],
+    ) -> Result {
+        let uri = otpauth::uri(credential, secret).ok_or(Status::NotEnoughMemory)?;
+        Self::push_tlv(reply, oath::Tag::Key, secret)?;
+        Self::push_tlv(reply, oath::Tag::OtpauthUri, uri.as_bytes())
+    }
+
+    fn register_uri<const R: usize>(
+        &mut self,
+        register_uri: command::RegisterUri<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let mut label = ShortData::new();
+        let mut issuer = ShortData::new();
+        let mut secret = otpauth::Secret::new();
+        let credential = command::Credential {
+            touch_required: register_uri.touch_required,
+            encryption_key_type: register_uri.encryption_key_type,
+            ..otpauth::parse(register_uri.uri, &mut label, &mut issuer, &mut secret)?
+        };
+        self.register(
+            command::Register {
+                credential,
+                generate_secret: false,
+            },
+            reply,
+        )
+    }
+
     fn filename_for_label(&mut self, label: &[u8]) -> trussed::types::PathBuf {
         let label_hash = syscall!(self.trussed.hash_sha256(label)).hash;
 
@@ -591,52 +998,171 @@ where
         calculate_all: command::CalculateAll<'_>,
         reply: &mut Data<R>,
     ) -> Result {
-        if !self.state.runtime.client_authorized {
-            return Err(Status::ConditionsOfUseNotSatisfied);
+        self.check_access(EncryptionKeyType::Hardware)?;
+
+        // Keep a copy of the challenge, in case the response would need a continuation
+        let challenge = heapless::Vec::from_slice(calculate_all.challenge)
+            .map_err(|_| Status::IncorrectDataParameter)?;
+
+        let pin_authorized = self.state.runtime.client_authorized;
+        self.calculate_all_from_index(
+            reply,
+            challenge,
+            calculate_all.response_truncated,
+            calculate_all.unix_time,
+            0,
+            pin_authorized,
+        )
+    }
+
+    #[cfg(feature = "calculate-all")]
+    fn calculate_all_from_index<const R: usize>(
+        &mut self,
+        reply: &mut Data<R>,
+        challenge: heapless::Vec<u8, 8>,
+        response_truncated: bool,
+        unix_time: bool,
+        file_index: usize,
+        pin_authorized: bool,
+    ) -> Result {
+        let mut maybe_file = self.read_credential_file_at_index(file_index)?;
+
+        let mut file_index = file_index;
+        while let Some(file) = maybe_file {
+            let maybe_credential = self.decrypt_credential(file, pin_authorized);
+            // Reverse HOTP and TOTP credentials are not to be used for the code generation,
+            // hence not listed. OCRA, S/KEY and recovery codes have their own commands.
+            if let Some(credential) = maybe_credential.filter(|c| {
+                !matches!(
+                    c.kind,
+                    oath::Kind::HotpReverse
+                        | oath::Kind::TotpReverse
+                        | oath::Kind::Ocra
+                        | oath::Kind::HashChain
+                        | oath::Kind::RecoveryCodes
+                )
+            }) {
+                let (tag, response) = self.calculate_all_response(
+                    &credential,
+                    &challenge,
+                    response_truncated,
+                    unix_time,
+                )?;
+
+                // Try to serialize, abort if not succeeded
+                let current_reply_bytes_count = reply.len();
+                let res = Self::try_to_serialize_credential_for_calculate_all(
+                    &credential,
+                    tag,
+                    &response,
+                    reply,
+                );
+                if res.is_err() {
+                    // Revert reply vector to the last good size, removing debris from the failed
+                    // serialization
+                    reply.truncate(current_reply_bytes_count);
+                    return Err(Status::MoreAvailable(0xFF));
+                }
+            }
+
+            // keep track, in case we need continuation
+            file_index += 1;
+            self.state.runtime.previously = Some(CommandState::CalculateAll {
+                file_index,
+                pin_authorized,
+                challenge: challenge.clone(),
+                response_truncated,
+                unix_time,
+            });
+
+            // check if there's more
+            maybe_file = syscall!(self.trussed.read_dir_files_next()).data;
         }
 
-        let maybe_credential_enc = syscall!(self.trussed.read_dir_files_first(
-            self.options.location,
-            Self::credential_directory(),
-            None
-        ))
-        .data;
-        let mut maybe_credential: Option<Credential> = match maybe_credential_enc {
-            None => None,
-            Some(c) => self.state.decrypt_content(&mut self.trussed, c).ok(),
-        };
+        // ran to completion
+        self.state.runtime.previously = None;
+        Ok(())
+    }
 
-        while let Some(credential) = maybe_credential {
-            // add to response
-            reply.push(0x71).unwrap();
-            reply.push(credential.label.len() as u8).unwrap();
-            reply.extend_from_slice(&credential.label).unwrap();
+    /// Returns the response tag and data for the given credential, as in YKOATH CALCULATE ALL.
+    /// Codes are calculated only for the TOTP credentials not requiring touch. For the rest
+    /// the client has to call CALCULATE separately.
+    #[cfg(feature = "calculate-all")]
+    fn calculate_all_response(
+        &mut self,
+        credential: &Credential,
+        challenge: &[u8],
+        response_truncated: bool,
+        unix_time: bool,
+    ) -> Result<(oath::Tag, Signature)> {
+        // Each credential may have its own period and T0
+        let time_step;
+        let challenge = if unix_time && credential.kind == oath::Kind::Totp {
+            time_step = Self::time_step_for_unix_time(credential, challenge)?;
+            &time_step[..]
+        } else {
+            challenge
+        };
 
-            // calculate the value
-            if credential.kind == oath::Kind::Totp {
+        Ok(match credential.kind {
+            oath::Kind::Totp if credential.touch_required => (oath::Tag::Touch, Signature::new()),
+            oath::Kind::Totp if response_truncated => {
                 let truncated_digest = crate::calculate::calculate(
                     &mut self.trussed,
                     credential.algorithm,
-                    calculate_all.challenge,
-                    credential.secret,
+                    challenge,
+                    credential.key()?,
                 )?;
-                reply.push(0x76).unwrap();
-                reply.push(5).unwrap();
-                reply.push(credential.digits).unwrap();
-                reply.extend_from_slice(&truncated_digest).unwrap();
-            } else {
-                reply.push(0x77).unwrap();
-                reply.push(0).unwrap();
-            };
+                match credential.output_format {
+                    Some(output_format) => (
+                        oath::Tag::FormattedResponse,
+                        Signature::from_slice(&crate::calculate::format_code(
+                            output_format,
+                            truncated_digest,
+                            credential.digits,
+                        ))
+                        .unwrap(),
+                    ),
+                    None => (
+                        oath::Tag::TruncatedResponse,
+                        Signature::from_slice(&truncated_digest).unwrap(),
+                    ),
+                }
+            }
+            oath::Kind::Totp => (
+                oath::Tag::Response,
+                crate::calculate::hmac(
+                    &mut self.trussed,
+                    credential.algorithm,
+                    challenge,
+                    credential.key()?,
+                )?,
+            ),
+            _ => (oath::Tag::Hotp, Signature::new()),
+        })
+    }
 
-            // check if there's more
-            maybe_credential = match syscall!(self.trussed.read_dir_files_next()).data {
-                None => None,
-                Some(c) => self.state.decrypt_content(&mut self.trussed, c).ok(),
-            };
+    #[cfg(feature = "calculate-all")]
+    fn try_to_serialize_credential_for_calculate_all<const R: usize>(
+        credential: &Credential,
+        tag: oath::Tag,
+        response: &[u8],
+        reply: &mut Data<R>,
+    ) -> core::result::Result<(), u8> {
+        reply.push(oath::Tag::Name as u8)?;
+        reply.push(credential.label.len() as u8)?;
+        reply.extend_from_slice(&credential.label).map_err(|_| 0)?;
+        // digits are sent with the Touch and Hotp tags as well
+        reply.push(tag as u8)?;
+        reply.push((response.len() + 1) as u8)?;
+        reply.push(credential.digits)?;
+        reply.extend_from_slice(response).map_err(|_| 0)?;
+        #[cfg(feature = "devel-ctaphid-bug")]
+        if reply.len() > 3072 {
+            // Finish early due to the usbd-ctaphid bug, which panics on bigger buffers than this
+            // FIXME Remove once fixed
+            return Err(1);
         }
-
-        // ran to completion
         Ok(())
     }
 
@@ -645,38 +1171,50 @@ where
         calculate: command::Calculate<'_>,
         reply: &mut Data<R>,
     ) -> Result {
-        if !self.state.runtime.client_authorized {
-            return Err(Status::ConditionsOfUseNotSatisfied);
-        }
         // info_now!("recv {:?}", &calculate);
 
         let credential = self
             .load_credential(calculate.label)
             .ok_or(Status::NotFound)?;
+        self.check_access(credential.encryption_key_type)?;
+
+        // The codes for the adjacent time steps are available only in the truncated form
+        if calculate.adjacent_time_steps.is_some() {
+            ensure(
+                credential.kind == oath::Kind::Totp && calculate.response_truncated,
+                Status::IncorrectDataParameter,
+            )?;
+        }
 
         if credential.touch_required {
             self.user_present()?;
         }
 
-        let truncated_digest = match credential.kind {
-            oath::Kind::Totp => crate::calculate::calculate(
-                &mut self.trussed,
-                credential.algorithm,
-                calculate.challenge,
-                credential.secret,
-            )?,
+        let counter_bytes;
+        let challenge = match credential.kind {
+            oath::Kind::Totp if calculate.unix_time => {
+                counter_bytes = Self::time_step_for_unix_time(&credential, calculate.challenge)?;
+                &counter_bytes[..]
+            }
+            oath::Kind::Totp => calculate.challenge,
             oath::Kind::Hotp => {
                 if let Some(counter) = credential.counter {
-                    self.calculate_hotp_digest_and_bump_counter(&credential, counter)?
+                    self.bump_counter_for_cred(&credential, counter)?;
+                    counter_bytes = counter.to_be_bytes();
+                    &counter_bytes[..]
                 } else {
                     error_now!("HOTP missing its counter");
                     return Err(Status::UnspecifiedPersistentExecutionError);
                 }
             }
-            Kind::HotpReverse => {
-                // This credential kind should never be access through calculate()
+            Kind::HotpReverse | Kind::TotpReverse => {
+                // These credential kinds should never be accessed through calculate()
                 return Err(Status::SecurityStatusNotSatisfied);
             }
+            Kind::Ocra | Kind::HashChain | Kind::RecoveryCodes => {
+                // Used with calculate_ocra(), calculate_hash_chain() and use_recovery_code()
+                return Err(Status::ConditionsOfUseNotSatisfied);
+            }
         };
 
         // SW: 71 0F 36 30 2F 73 6F 6C 6F 6B 65 79 73 37 5F 36 30 76 05 07 3D 8E 94 CF 90 00
@@ -691,10 +1229,291 @@ where
         // response.push(credential.label.len() as u8).unwrap();
         // response.extend_from_slice(credential.label).unwrap();
 
-        reply.push(0x76).unwrap();
-        reply.push(5).unwrap();
-        reply.push(credential.digits).unwrap();
-        reply.extend_from_slice(&truncated_digest).unwrap();
+        if let Some((steps_before, steps_after)) = calculate.adjacent_time_steps {
+            // One truncated response per time step, starting from the earliest one
+            let time_step = u64::from_be_bytes(
+                challenge
+                    .try_into()
+                    .map_err(|_| Status::IncorrectDataParameter)?,
+            );
+            let first_time_step = time_step
+                .checked_sub(steps_before as u64)
+                .ok_or(Status::IncorrectDataParameter)?;
+            let last_time_step = time_step
+                .checked_add(steps_after as u64)
+                .ok_or(Status::IncorrectDataParameter)?;
+            for time_step in first_time_step..=last_time_step {
+                let truncated_digest = crate::calculate::calculate(
+                    &mut self.trussed,
+                    credential.algorithm,
+                    &time_step.to_be_bytes(),
+                    credential.key()?,
+                )?;
+                Self::push_truncated_response(reply, &credential, truncated_digest)?;
+            }
+        } else if calculate.response_truncated {
+            let truncated_digest = crate::calculate::calculate(
+                &mut self.trussed,
+                credential.algorithm,
+                challenge,
+                credential.key()?,
+            )?;
+            Self::push_truncated_response(reply, &credential, truncated_digest)?;
+        } else {
+            // 20, 32 or 64 bytes of the HMAC digest, depending on the algorithm
+            let digest = crate::calculate::hmac(
+                &mut self.trussed,
+                credential.algorithm,
+                challenge,
+                credential.key()?,
+            )?;
+            Self::push_response(reply, oath::Tag::Response, credential.digits, &digest)?;
+        }
+        Ok(())
+    }
+
+    /// Append the truncated response, or the final code string, if the credential has it
+    /// formatted on the device
+    fn push_truncated_response<const R: usize>(
+        reply: &mut Data<R>,
+        credential: &Credential,
+        truncated_digest: [u8; 4],
+    ) -> Result {
+        let (tag, code) = match credential.output_format {
+            Some(output_format) => (
+                oath::Tag::FormattedResponse,
+                crate::calculate::format_code(output_format, truncated_digest, credential.digits),
+            ),
+            None => (
+                oath::Tag::TruncatedResponse,
+                FormattedCode::from_slice(&truncated_digest).unwrap(),
+            ),
+        };
+        Self::push_response(reply, tag, credential.digits, &code)
+    }
+
+    /// Convert the Unix time sent as the challenge to the TOTP time step of the credential
+    fn time_step_for_unix_time(credential: &Credential, challenge: &[u8]) -> Result<[u8; 8]> {
+        let unix_time = u64::from_be_bytes(
+            challenge
+                .try_into()
+                .map_err(|_| Status::IncorrectDataParameter)?,
+        );
+        let time_step = credential
+            .totp_time_step(unix_time)
+            .ok_or(Status::IncorrectDataParameter)?;
+        Ok(time_step.to_be_bytes())
+    }
+
+    /// Calculate the OCRA response, as in [RFC 6287][rfc-6287]. The DataInput is built from
+    /// the credential's suite and counter, and the question, PIN hash, session information
+    /// and timestamp sent by the host. The counter is bumped in the same way as for HOTP.
+    ///
+    /// [rfc-6287]: https://tools.ietf.org/html/rfc6287
+    fn calculate_ocra<const R: usize>(
+        &mut self,
+        calculate: command::CalculateOcra<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let credential = self
+            .load_credential(calculate.label)
+            .ok_or(Status::NotFound)?;
+        self.check_access(credential.encryption_key_type)?;
+        ensure(
+            credential.kind == oath::Kind::Ocra,
+            Status::ConditionsOfUseNotSatisfied,
+        )?;
+
+        let suite_string = credential
+            .ocra_suite
+            .as_ref()
+            .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+        let suite =
+            ocra::Suite::parse(suite_string).ok_or(Status::UnspecifiedPersistentExecutionError)?;
+        // Suites without the truncation return only the full HMAC
+        ensure(
+            suite.digits != 0 || !calculate.response_truncated,
+            Status::IncorrectDataParameter,
+        )?;
+        let data_input = suite.data_input(
+            suite_string,
+            credential.counter,
+            &ocra::Inputs {
+                question: calculate.question,
+                pin_hash: calculate.pin_hash,
+                session: calculate.session,
+                unix_time: calculate.unix_time,
+            },
+        )?;
+
+        if credential.touch_required {
+            self.user_present()?;
+        }
+
+        if let Some(counter) = credential.counter {
+            self.bump_counter_for_cred(&credential, counter)?;
+        }
+
+        if calculate.response_truncated {
+            let truncated_digest = crate::calculate::calculate(
+                &mut self.trussed,
+                credential.algorithm,
+                &data_input,
+                credential.key()?,
+            )?;
+            Self::push_response(
+                reply,
+                oath::Tag::TruncatedResponse,
+                credential.digits,
+                &truncated_digest,
+            )?;
+        } else {
+            let digest = crate::calculate::hmac(
+                &mut self.trussed,
+                credential.algorithm,
+                &data_input,
+                credential.key()?,
+            )?;
+            Self::push_response(reply, oath::Tag::Response, credential.digits, &digest)?;
+        }
+        Ok(())
+    }
+
+    /// Return the S/KEY one-time password for the current sequence number, as in
+    /// [RFC 2289][rfc-2289], and decrement it. Refused once the sequence number reaches zero,
+    /// or if it is not the one expected by the host. The sequence number is stored before
+    /// the OTP is returned, so it is never reused.
+    ///
+    /// [rfc-2289]: https://tools.ietf.org/html/rfc2289
+    fn calculate_hash_chain<const R: usize>(
+        &mut self,
+        calculate: command::CalculateHashChain<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let mut credential = self
+            .load_credential(calculate.label)
+            .ok_or(Status::NotFound)?;
+        self.check_access(credential.encryption_key_type)?;
+        ensure(
+            credential.kind == oath::Kind::HashChain,
+            Status::ConditionsOfUseNotSatisfied,
+        )?;
+
+        let (sequence, passphrase_key) = match (credential.counter, credential.hash_chain_key) {
+            (Some(sequence), Some(passphrase_key)) => (sequence, passphrase_key),
+            _ => return Err(Status::UnspecifiedPersistentExecutionError),
+        };
+        let sequence: u16 = sequence
+            .try_into()
+            .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+        ensure(sequence > 0, Status::OperationBlocked)?;
+        ensure(
+            calculate
+                .sequence
+                .map_or(true, |expected| expected == sequence),
+            Status::IncorrectDataParameter,
+        )?;
+
+        if credential.touch_required {
+            self.user_present()?;
+        }
+
+        credential.counter = Some((sequence - 1).into());
+        let filename = self.filename_for_label(&credential.label);
+        self.state.try_write_file(
+            &mut self.trussed,
+            filename,
+            &credential,
+            credential.encryption_key_type,
+        )?;
+
+        let otp = hash_chain::otp(credential.algorithm, passphrase_key, sequence)
+            .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+        Self::push_tlv(reply, oath::Tag::HashChainSequence, &sequence.to_be_bytes())?;
+        if calculate.hex {
+            Self::push_tlv(reply, oath::Tag::HashChainOtp, &hash_chain::hex(otp))?;
+        } else {
+            let mut indices = [0u8; 12];
+            for (chunk, index) in indices.chunks_mut(2).zip(hash_chain::word_indices(otp)) {
+                chunk.copy_from_slice(&index.to_be_bytes());
+            }
+            Self::push_tlv(reply, oath::Tag::HashChainOtp, &indices)?;
+        }
+        Ok(())
+    }
+
+    /// Return the next unused recovery code after the touch confirmation, or verify the code
+    /// sent by the host. Either way the code is removed from the list, so it can be used once only.
+    /// The number of the remaining codes is returned as well. The failed verifications are
+    /// limited per power cycle, together with the Reverse HOTP ones.
+    fn use_recovery_code<const R: usize>(
+        &mut self,
+        args: command::UseRecoveryCode<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let mut credential = self.load_credential(args.label).ok_or(Status::NotFound)?;
+        self.check_access(credential.encryption_key_type)?;
+        ensure(
+            credential.kind == oath::Kind::RecoveryCodes,
+            Status::ConditionsOfUseNotSatisfied,
+        )?;
+        let list = credential
+            .recovery_codes
+            .take()
+            .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+
+        let index = match args.code {
+            Some(code) => {
+                self.check_failures_left()?;
+                if credential.touch_required {
+                    self.user_present()?;
+                }
+                match recovery_codes::position(&list, code) {
+                    Some(index) => {
+                        self.state.runtime.failures = 0;
+                        index
+                    }
+                    None => {
+                        self.wink_bad();
+                        self.count_failure();
+                        return Err(Status::VerificationFailed);
+                    }
+                }
+            }
+            None => {
+                // Revealing the code always needs the touch confirmation
+                ensure(
+                    recovery_codes::iter(&list).next().is_some(),
+                    Status::OperationBlocked,
+                )?;
+                self.user_present()?;
+                0
+            }
+        };
+
+        // Burn the code before it is returned
+        credential.recovery_codes = Some(recovery_codes::without(&list, index));
+        let filename = self.filename_for_label(&credential.label);
+        self.state.try_write_file(
+            &mut self.trussed,
+            filename,
+            &credential,
+            credential.encryption_key_type,
+        )?;
+
+        if args.code.is_none() {
+            let code = recovery_codes::iter(&list)
+                .nth(index)
+                .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+            Self::push_tlv(reply, oath::Tag::RecoveryCode, code)?;
+        } else {
+            self.wink_good();
+        }
+        Self::push_tlv(
+            reply,
+            oath::Tag::RecoveryCodesRemaining,
+            &[credential.recovery_codes_remaining() as u8],
+        )?;
         Ok(())
     }
 
@@ -711,7 +1530,7 @@ where
 
         if let Some(key) = self
             .state
-            .with_persistent(&mut self.trussed, |_, state| state.authorization_key)
+            .with_persistent(&mut self.trussed, |_, state| state.authorization_key)?
         {
             debug_now!("key set: {:?}", key);
 
@@ -908,40 +1727,55 @@ where
     /// Does not need authorization by design.
     ///
     /// https://github.com/Nitrokey/nitrokey-hotp-verification#verifying-hotp-code
-    /// Solution contains a mean to avoid desynchronization between the host's and device's counters. Device calculates up to 9 values ahead of its current counter to find the matching code (in total it calculates HOTP code for 10 subsequent counter positions). In case:
+    /// Solution contains a mean to avoid desynchronization between the host's and device's counters. Device calculates up to the credential's window size (9 by default) values ahead of its current counter to find the matching code (in total it calculates HOTP code for window + 1 subsequent counter positions). In case:
     ///
     /// - no code would match - the on-device counter will not be changed;
     /// - incoming code parsing would fail - the on-device counter will not be changed;
-    /// - code would match, but with some counter's offset (up to 9) - the on-device counter will be set to matched code-generated HOTP counter and incremented by 1;
+    /// - code would match, but with some counter's offset (up to the window size) - the on-device counter will be set to matched code-generated HOTP counter and incremented by 1;
     /// - code would match, and the code matches counter without offset - the counter will be incremented by 1.
     ///
-    /// Device will stop verifying the HOTP codes in case, when the difference between the host and on-device counters will be greater or equal to 10.
+    /// Device will stop verifying the HOTP codes in case, when the difference between the host and on-device counters will be greater than the window size.
+    ///
+    /// The Reverse TOTP codes are checked in the same way, against the time steps around the one
+    /// sent by the host. Only the time steps after the last accepted one are checked.
+    ///
+    /// Failed verifications are counted across the power cycles, and the remaining attempts are
+    /// reported in the failure status. The command is locked after all are used, until the next
+    /// successful counter resynchronization. On top of that, only a few failures in a row are
+    /// allowed per power cycle.
     fn verify_code<const R: usize>(&mut self, args: VerifyCode, reply: &mut Data<{ R }>) -> Result {
-        const COUNTER_WINDOW_SIZE: u32 = 9;
-        let credential = self.load_credential(args.label).ok_or(Status::NotFound)?;
+        let (credential, current_counter) = self.load_reverse_credential(args.label)?;
+
+        // For the Reverse TOTP the counter holds the lowest time step not used yet,
+        // so the replayed codes are rejected
+        let (first_counter, window) = match credential.kind {
+            oath::Kind::TotpReverse => {
+                let time_step = args.time_step.ok_or(Status::IncorrectDataParameter)?;
+                let first_time_step = time_step.saturating_sub(REVERSE_TOTP_WINDOW);
+                let last_time_step = time_step.saturating_add(REVERSE_TOTP_WINDOW);
+                if last_time_step < current_counter {
+                    self.wink_bad();
+                    return Err(Status::VerificationFailed);
+                }
+                let first_counter = first_time_step.max(current_counter);
+                (first_counter, last_time_step - first_counter)
+            }
+            _ => (current_counter, credential.reverse_hotp_window() as u64),
+        };
 
+        self.check_failures_left()?;
         if credential.touch_required {
             self.user_present()?;
         }
 
+        let retries = self.count_verify_code_attempt()?;
         let code_in = args.response;
 
-        let current_counter = match credential.kind {
-            oath::Kind::HotpReverse => {
-                if let Some(counter) = credential.counter {
-                    counter
-                } else {
-                    debug_now!("HOTP missing its counter");
-                    return Err(Status::UnspecifiedPersistentExecutionError);
-                }
-            }
-            _ => return Err(Status::ConditionsOfUseNotSatisfied),
-        };
         let mut found = None;
-        for offset in 0..=COUNTER_WINDOW_SIZE {
+        for offset in 0..=window {
             // Do abort with error on the max value, so these could not be pregenerated,
             // and returned to user after overflow, or the same code used each time
-            let counter = current_counter
+            let counter = first_counter
                 .checked_add(offset)
                 .ok_or(Status::UnspecifiedPersistentExecutionError)?;
             let code = self
@@ -957,13 +1791,15 @@ where
             None => {
                 // Failed verification
                 self.wink_bad();
-                self.delay_on_failure();
-                return Err(Status::VerificationFailed);
+                self.count_failure();
+                return Err(Status::RemainingRetries(retries));
             }
             Some(val) => val,
         };
 
         self.bump_counter_for_cred(&credential, found)?;
+        self.reset_verify_code_failures()?;
+        self.state.runtime.failures = 0;
         self.wink_good();
 
         // Verification passed
@@ -973,10 +1809,214 @@ where
         Ok(())
     }
 
+    /// Resynchronize the Reverse HOTP counter, which drifted past the look-ahead window,
+    /// as described in RFC 4226, section 7.4. The host sends two consecutive codes, and these
+    /// are searched for in a much larger window. The counter is set after the second code,
+    /// only if both match.
+    /// Requires touch, and the PIN verification, if the PIN is set. Unlocks the VerifyCode command
+    /// on success. The failed attempts are limited per power cycle, together with the verification.
+    fn resync_code(&mut self, args: ResyncCode) -> Result {
+        if self._extension_is_pin_set()? && !self.state.runtime.client_authorized {
+            return Err(Status::ConditionsOfUseNotSatisfied);
+        }
+        let (credential, current_counter) = self.load_reverse_credential(args.label)?;
+        ensure(
+            credential.kind == oath::Kind::HotpReverse,
+            Status::ConditionsOfUseNotSatisfied,
+        )?;
+        self.check_failures_left()?;
+
+        self.user_present()?;
+
+        let mut previous_code = None;
+        let mut found = None;
+        for offset in 0..=REVERSE_HOTP_RESYNC_WINDOW + 1 {
+            let counter = current_counter
+                .checked_add(offset)
+                .ok_or(Status::UnspecifiedPersistentExecutionError)?;
+            let code = self
+                .calculate_hotp_code_for_counter(&credential, counter)
+                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
+            if previous_code == Some(args.response) && code == args.next_response {
+                found = Some(counter);
+                break;
+            }
+            previous_code = Some(code);
+        }
+
+        let found = match found {
+            None => {
+                self.wink_bad();
+                self.count_failure();
+                return Err(Status::VerificationFailed);
+            }
+            Some(val) => val,
+        };
+
+        self.bump_counter_for_cred(&credential, found)?;
+        self.reset_verify_code_failures()?;
+        self.state.runtime.failures = 0;
+        self.wink_good();
+        Ok(())
+    }
+
+    /// Return the current counter, kind, algorithm and digits of the Reverse HOTP credential,
+    /// so the host could tell how far its counter diverged. No code is calculated.
+    /// For the Reverse TOTP the counter is the lowest time step accepted in the next verification.
+    /// Does not need authorization, same as the code verification.
+    fn get_counter<const R: usize>(
+        &mut self,
+        get_counter: command::GetCounter<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let (credential, counter) = self.load_reverse_credential(get_counter.label)?;
+
+        Self::push_tlv(reply, oath::Tag::Name, &credential.label)?;
+        Self::push_tlv(
+            reply,
+            oath::Tag::Algorithm,
+            &[oath::combine(credential.kind, credential.algorithm)],
+        )?;
+        Self::push_tlv(reply, oath::Tag::Digits, &[credential.digits])?;
+        Self::push_tlv(
+            reply,
+            oath::Tag::InitialMovingFactor64,
+            &counter.to_be_bytes(),
+        )?;
+        Self::push_tlv(
+            reply,
+            oath::Tag::ReverseHotpWindow,
+            &[credential.reverse_hotp_window()],
+        )?;
+        Ok(())
+    }
+
+    /// Count the Reverse HOTP verification attempt as failed before it is made, so it would not
+    /// be lost on a power cut. Returns the attempts remaining after it.
+    fn count_verify_code_attempt(&mut self) -> Result<u8> {
+        self.state
+            .try_with_persistent_mut(&mut self.trussed, |_, state| {
+                if state.verify_code_retries() == 0 {
+                    return Err(Status::OperationBlocked);
+                }
+                state.verify_code_failures += 1;
+                Ok(state.verify_code_retries())
+            })
+    }
+
+    fn reset_verify_code_failures(&mut self) -> Result {
+        self.state
+            .try_with_persistent_mut(&mut self.trussed, |_, state| {
+                state.verify_code_failures = 0;
+                Ok(())
+            })
+    }
+
+    /// Load the Reverse HOTP or TOTP credential with its current counter
+    fn load_reverse_credential(&mut self, label: &[u8]) -> Result<(Credential, u64)> {
+        let mut credential = self.load_credential(label).ok_or(Status::NotFound)?;
+        // Reverse HOTP credentials stored by the previous versions are PIN-based. Move them
+        // under the device-bound key with the counter bump, so these work at boot from then on.
+        credential.encryption_key_type = EncryptionKeyType::Hardware;
+
+        let current_counter = match credential.kind {
+            oath::Kind::HotpReverse | oath::Kind::TotpReverse => {
+                if let Some(counter) = credential.counter {
+                    counter
+                } else {
+                    debug_now!("HOTP missing its counter");
+                    return Err(Status::UnspecifiedPersistentExecutionError);
+                }
+            }
+            _ => return Err(Status::ConditionsOfUseNotSatisfied),
+        };
+        Ok((credential, current_counter))
+    }
+
+    /// Return the Password Safe fields of the credential, along with the Reverse HOTP window
+    /// and the number of the remaining recovery codes for these kinds.
+    /// Needs PIN verification for all credentials, and touch, if the credential was registered
+    /// with it.
+    fn get_credential<const R: usize>(
+        &mut self,
+        get_credential: command::GetCredential<'_>,
+        reply: &mut Data<R>,
+    ) -> Result {
+        let credential = self
+            .load_credential(get_credential.label)
+            .ok_or(Status::NotFound)?;
+
+        if credential.touch_required {
+            self.user_present()?;
+        }
+
+        Self::push_tlv(reply, oath::Tag::Name, &credential.label)?;
+        for (tag, field) in [
+            (oath::Tag::PwsLogin, &credential.login),
+            (oath::Tag::PwsPassword, &credential.password),
+            (oath::Tag::PwsMetadata, &credential.metadata),
+        ] {
+            if let Some(value) = field {
+                Self::push_tlv(reply, tag, value)?;
+            }
+        }
+        if credential.kind == oath::Kind::HotpReverse {
+            Self::push_tlv(
+                reply,
+                oath::Tag::ReverseHotpWindow,
+                &[credential.reverse_hotp_window()],
+            )?;
+        }
+        if credential.kind == oath::Kind::RecoveryCodes {
+            Self::push_tlv(
+                reply,
+                oath::Tag::RecoveryCodesRemaining,
+                &[credential.recovery_codes_remaining() as u8],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Append the response TLV, with the digits count prepended to the (truncated) digest
+    fn push_response<const R: usize>(
+        reply: &mut Data<R>,
+        tag: oath::Tag,
+        digits: u8,
+        response: &[u8],
+    ) -> Result {
+        reply.push(tag as u8).map_err(|_| Status::NotEnoughMemory)?;
+        reply
+            .push((response.len() + 1) as u8)
+            .map_err(|_| Status::NotEnoughMemory)?;
+        reply.push(digits).map_err(|_| Status::NotEnoughMemory)?;
+        reply
+            .extend_from_slice(response)
+            .map_err(|_| Status::NotEnoughMemory)
+    }
+
+    /// Append a BER-TLV encoded value, using the long form of the length when needed
+    fn push_tlv<const R: usize>(reply: &mut Data<R>, tag: oath::Tag, value: &[u8]) -> Result {
+        reply.push(tag as u8).map_err(|_| Status::NotEnoughMemory)?;
+        let length = (value.len() as u16).to_be_bytes();
+        let header = [0x82, length[0], 0x81];
+        let header = match value.len() {
+            0..=0x7f => &header[..0],
+            0x80..=0xff => &header[2..],
+            _ => &header[..2],
+        };
+        reply
+            .extend_from_slice(header)
+            .map_err(|_| Status::NotEnoughMemory)?;
+        reply.push(length[1]).map_err(|_| Status::NotEnoughMemory)?;
+        reply
+            .extend_from_slice(value)
+            .map_err(|_| Status::NotEnoughMemory)
+    }
+
     fn calculate_hotp_code_for_counter(
         &mut self,
         credential: &Credential,
-        counter: u32,
+        counter: u64,
     ) -> iso7816::Result<u32> {
         let truncated_digest = self.calculate_hotp_digest_for_counter(credential, counter)?;
         let truncated_code = u32::from_be_bytes(truncated_digest);
@@ -988,20 +2028,10 @@ where
         Ok(code)
     }
 
-    fn calculate_hotp_digest_and_bump_counter(
-        &mut self,
-        credential: &Credential,
-        counter: u32,
-    ) -> iso7816::Result<[u8; 4]> {
-        let credential = self.bump_counter_for_cred(credential, counter)?;
-        let res = self.calculate_hotp_digest_for_counter(&credential, counter)?;
-        Ok(res)
-    }
-
     fn bump_counter_for_cred(
         &mut self,
         credential: &Credential,
-        counter: u32,
+        counter: u64,
     ) -> Result<Credential> {
         // Do abort with error on the max value, so these could not be pregenerated,
         // and returned to user after overflow, or the same code used each time
@@ -1014,8 +2044,12 @@ where
         );
         // save credential back, with the updated counter
         let filename = self.filename_for_label(&credential.label);
-        self.state
-            .try_write_file(&mut self.trussed, filename, &credential)?;
+        self.state.try_write_file(
+            &mut self.trussed,
+            filename,
+            &credential,
+            credential.encryption_key_type,
+        )?;
 
         Ok(credential)
     }
@@ -1023,14 +2057,13 @@ where
     fn calculate_hotp_digest_for_counter(
         &mut self,
         credential: &Credential,
-        counter: u32,
+        counter: u64,
     ) -> Result<[u8; 4]> {
-        let counter_long: u64 = counter.into();
         crate::calculate::calculate(
             &mut self.trussed,
             credential.algorithm,
-            &counter_long.to_be_bytes(),
-            credential.secret,
+            &counter.to_be_bytes(),
+            credential.key()?,
         )
     }
 
@@ -1184,10 +2217,16 @@ where
         syscall!(self.trussed.wink(Duration::from_secs(10)));
     }
 
-    fn delay_on_failure(&mut self) {
+    /// Refuse the code verification, once all the failures allowed in this power cycle are used
+    fn check_failures_left(&self) -> Result {
+        ensure(
+            self.state.runtime.failures < FAILURES_PER_POWER_CYCLE_MAX,
+            Status::OperationBlocked,
+        )
+    }
 
-        // TODO block for the time defined in the constant
-        // DESIGN allow only a couple of failures per power cycle? Similarly to the FIDO2 PIN
+    fn count_failure(&mut self) {
+        self.state.runtime.failures = self.state.runtime.failures.saturating_add(1);
     }
 }
 
@@ -1203,6 +2242,7 @@ where
     T: client::Client
         + client::HmacSha1
         + client::HmacSha256
+        + client::HmacSha512
         + client::Sha256
         + client::Chacha8Poly1305
         + trussed_auth::AuthClient,
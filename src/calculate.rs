@@ -3,7 +3,10 @@ use iso7816::Status;
 
 use crate::oath;
 use crate::Result;
-use trussed::{client, try_syscall, types::KeyId};
+use trussed::{
+    client, try_syscall,
+    types::{KeyId, Signature},
+};
 
 /// The core calculation
 ///
@@ -19,27 +22,59 @@ pub fn calculate<T>(
     key: KeyId,
 ) -> Result<[u8; 4]>
 where
-    T: client::Client + client::HmacSha1 + client::HmacSha256 + client::Sha256,
+    T: client::Client + client::HmacSha1 + client::HmacSha256 + client::HmacSha512 + client::Sha256,
+{
+    let digest = hmac(trussed, algorithm, challenge, key)?;
+    let truncated = dynamic_truncation(&digest);
+    Ok(truncated.to_be_bytes())
+}
+
+/// The full HMAC digest of the challenge, before the dynamic truncation.
+/// Its length depends on the algorithm: 20 bytes for SHA1, 32 for SHA256 and 64 for SHA512.
+pub fn hmac<T>(
+    trussed: &mut T,
+    algorithm: oath::Algorithm,
+    challenge: &[u8],
+    key: KeyId,
+) -> Result<Signature>
+where
+    T: client::Client + client::HmacSha1 + client::HmacSha256 + client::HmacSha512 + client::Sha256,
 {
     use oath::Algorithm::*;
-    let truncated = match algorithm {
-        Sha1 => {
-            let digest = try_syscall!(trussed.sign_hmacsha1(key, challenge))
-                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?
-                .signature;
-            dynamic_truncation(&digest)
-        }
-        Sha256 => {
-            let digest = try_syscall!(trussed.sign_hmacsha256(key, challenge))
-                .map_err(|_| Status::UnspecifiedPersistentExecutionError)?
-                .signature;
-            dynamic_truncation(&digest)
-        }
-        // Sha512 => unimplemented!(),
-        Sha512 => return Err(Status::FunctionNotSupported),
-    };
+    let digest = match algorithm {
+        Sha1 => try_syscall!(trussed.sign_hmacsha1(key, challenge)),
+        Sha256 => try_syscall!(trussed.sign_hmacsha256(key, challenge)),
+        Sha512 => try_syscall!(trussed.sign_hmacsha512(key, challenge)),
+        Md5 => return Err(Status::IncorrectDataParameter),
+    }
+    .map_err(|_| Status::UnspecifiedPersistentExecutionError)?
+    .signature;
+    Ok(digest)
+}
 
-    Ok(truncated.to_be_bytes())
+/// Steam Guard code alphabet
+const STEAM_ALPHABET: &[u8; 26] = b"23456789BCDFGHJKMNPQRTVWXY";
+
+/// The final code string, for up to 10 characters
+pub type FormattedCode = heapless::Vec<u8, 10>;
+
+/// Format the dynamically truncated digest to the final code string on the device.
+/// For Steam Guard the characters are taken from the least significant ones, base 26.
+pub fn format_code(
+    output_format: oath::OutputFormat,
+    truncated: [u8; 4],
+    length: u8,
+) -> FormattedCode {
+    let mut value = u32::from_be_bytes(truncated);
+    match output_format {
+        oath::OutputFormat::Steam => (0..length)
+            .map(|_| {
+                let c = STEAM_ALPHABET[(value % STEAM_ALPHABET.len() as u32) as usize];
+                value /= STEAM_ALPHABET.len() as u32;
+                c
+            })
+            .collect(),
+    }
 }
 
 fn dynamic_truncation(digest: &[u8]) -> u32 {
@@ -2,7 +2,10 @@ use core::convert::{TryFrom, TryInto};
 
 use iso7816::{Data, Status};
 
-use crate::{ensure, oath};
+use crate::{
+    ensure, hash_chain, oath, ocra, recovery_codes, state::EncryptionKeyType,
+    HASH_CHAIN_SEQUENCE_MAX, REVERSE_HOTP_WINDOW_MAX, TOTP_ADJACENT_TIME_STEPS_MAX,
+};
 
 const FAILED_PARSING_ERROR: Status = iso7816::Status::IncorrectDataParameter;
 
@@ -14,14 +17,24 @@ pub enum Command<'l> {
     Calculate(Calculate<'l>),
     /// Calculate the authentication data for all credentials.
     CalculateAll(CalculateAll<'l>),
+    /// Calculate the OCRA response for a credential given by label.
+    CalculateOcra(CalculateOcra<'l>),
+    /// Get the next S/KEY one-time password of a credential given by label.
+    CalculateHashChain(CalculateHashChain<'l>),
+    /// Get the next recovery code of a credential given by label, or verify the given one.
+    UseRecoveryCode(UseRecoveryCode<'l>),
     /// Clear the password.
     ClearPassword,
     /// Delete a credential.
     Delete(Delete<'l>),
     /// List all credentials.
-    ListCredentials,
+    ListCredentials(ListCredentials),
     /// Register a new credential.
     Register(Register<'l>),
+    /// Register a new credential from its otpauth:// URI.
+    RegisterUri(RegisterUri<'l>),
+    /// Rename an existing credential.
+    Rename(Rename<'l>),
     /// Delete all credentials and rotate the salt.
     Reset,
     /// Set a password.
@@ -36,6 +49,12 @@ pub enum Command<'l> {
     ChangePin(ChangePin<'l>),
     /// Reverse HOTP validation
     VerifyCode(VerifyCode<'l>),
+    /// Reverse HOTP counter resynchronization with two consecutive codes
+    ResyncCode(ResyncCode<'l>),
+    /// Get the Reverse HOTP counter and parameters, without any code
+    GetCounter(GetCounter<'l>),
+    /// Get the Password Safe fields of a credential
+    GetCredential(GetCredential<'l>),
     /// Send remaining data in the buffer
     SendRemaining,
 }
@@ -160,6 +179,8 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Validate<'l> {
 pub struct VerifyCode<'l> {
     pub label: &'l [u8],
     pub response: u32,
+    /// Time step of the code, required for the Reverse TOTP
+    pub time_step: Option<u64>,
 }
 
 impl<'l, const C: usize> TryFrom<&'l Data<C>> for VerifyCode<'l> {
@@ -187,7 +208,118 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for VerifyCode<'l> {
                 .map_err(|_| FAILED_PARSING_ERROR)?,
         );
 
-        Ok(VerifyCode { label, response })
+        // the time step follows for the Reverse TOTP, with Tag::Challenge
+        let maybe_time_step: Option<TaggedSlice> =
+            decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        let time_step = match maybe_time_step {
+            Some(slice) => {
+                ensure(
+                    slice.tag() == (oath::Tag::Challenge as u8).try_into().unwrap(),
+                    FAILED_PARSING_ERROR,
+                )?;
+                let bytes: [u8; 8] = slice
+                    .as_bytes()
+                    .try_into()
+                    .map_err(|_| FAILED_PARSING_ERROR)?;
+                Some(u64::from_be_bytes(bytes))
+            }
+            None => None,
+        };
+
+        Ok(VerifyCode {
+            label,
+            response,
+            time_step,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ResyncCode<'l> {
+    pub label: &'l [u8],
+    pub response: u32,
+    pub next_response: u32,
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for ResyncCode<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        // two consecutive codes, each with its own Tag::Response
+        let mut responses = [0u32; 2];
+        for response in responses.iter_mut() {
+            let slice: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+            ensure(
+                slice.tag() == (oath::Tag::Response as u8).try_into().unwrap(),
+                FAILED_PARSING_ERROR,
+            )?;
+            *response = u32::from_be_bytes(
+                slice
+                    .as_bytes()
+                    .try_into()
+                    .map_err(|_| FAILED_PARSING_ERROR)?,
+            );
+        }
+        let [response, next_response] = responses;
+
+        Ok(ResyncCode {
+            label,
+            response,
+            next_response,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GetCounter<'l> {
+    pub label: &'l [u8],
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for GetCounter<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        Ok(GetCounter { label })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GetCredential<'l> {
+    pub label: &'l [u8],
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for GetCredential<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        Ok(GetCredential { label })
     }
 }
 
@@ -272,6 +404,14 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for VerifyPin<'l> {
 pub struct Calculate<'l> {
     pub label: &'l [u8],
     pub challenge: &'l [u8],
+    /// Return the dynamically truncated digest (P2 = 0x01), instead of the full HMAC (P2 = 0x00)
+    pub response_truncated: bool,
+    /// The challenge is the Unix time (P1 = 0x01), to be converted to the TOTP time step
+    /// with the credential's period and T0
+    pub unix_time: bool,
+    /// Number of the time steps before and after the challenge, to calculate the TOTP codes for
+    /// along the one for the challenge
+    pub adjacent_time_steps: Option<(u8, u8)>,
 }
 
 impl<'l, const C: usize> TryFrom<&'l Data<C>> for Calculate<'l> {
@@ -294,13 +434,45 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Calculate<'l> {
         )?;
         let challenge = second.as_bytes();
 
-        Ok(Calculate { label, challenge })
+        let maybe_adjacent_time_steps: Option<TaggedSlice> =
+            decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        let adjacent_time_steps = match maybe_adjacent_time_steps {
+            Some(slice) => {
+                ensure(
+                    slice.tag() == (oath::Tag::AdjacentTimeSteps as u8).try_into().unwrap(),
+                    FAILED_PARSING_ERROR,
+                )?;
+                let [before, after]: [u8; 2] = slice
+                    .as_bytes()
+                    .try_into()
+                    .map_err(|_| FAILED_PARSING_ERROR)?;
+                ensure(
+                    before <= TOTP_ADJACENT_TIME_STEPS_MAX && after <= TOTP_ADJACENT_TIME_STEPS_MAX,
+                    FAILED_PARSING_ERROR,
+                )?;
+                Some((before, after))
+            }
+            None => None,
+        };
+
+        Ok(Calculate {
+            label,
+            challenge,
+            response_truncated: true,
+            unix_time: false,
+            adjacent_time_steps,
+        })
     }
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct CalculateAll<'l> {
     pub challenge: &'l [u8],
+    /// Return the dynamically truncated digests (P2 = 0x01), instead of the full HMAC (P2 = 0x00)
+    pub response_truncated: bool,
+    /// The challenge is the Unix time (P1 = 0x01), to be converted to the TOTP time step
+    /// for each credential
+    pub unix_time: bool,
 }
 
 impl<'l, const C: usize> TryFrom<&'l Data<C>> for CalculateAll<'l> {
@@ -316,10 +488,181 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for CalculateAll<'l> {
         )?;
         let challenge = first.as_bytes();
 
-        Ok(CalculateAll { challenge })
+        Ok(CalculateAll {
+            challenge,
+            response_truncated: true,
+            unix_time: false,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CalculateOcra<'l> {
+    pub label: &'l [u8],
+    pub question: &'l [u8],
+    /// Return the dynamically truncated digest (P2 = 0x01), instead of the full HMAC (P2 = 0x00)
+    pub response_truncated: bool,
+    /// The rest of the DataInput, as required by the credential's suite
+    pub pin_hash: Option<&'l [u8]>,
+    pub session: Option<&'l [u8]>,
+    pub unix_time: Option<u64>,
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for CalculateOcra<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::Decodable;
+        type TaggedSlice<'a> = flexiber::TaggedSlice<'a, flexiber::SimpleTag>;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        let second: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            second.tag() == (oath::Tag::Challenge as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let question = second.as_bytes();
+
+        let mut pin_hash = None;
+        let mut session = None;
+        let mut unix_time = None;
+
+        // the rest of the fields is optional, while a malformed one is refused
+        while !decoder.is_finished() {
+            let tlv = TaggedSlice::decode(&mut decoder).map_err(|_| FAILED_PARSING_ERROR)?;
+            let is_tag = |tag: oath::Tag| tlv.tag() == (tag as u8).try_into().unwrap();
+            let bytes = tlv.as_bytes();
+            if is_tag(oath::Tag::OcraPinHash) {
+                pin_hash = Some(bytes);
+            } else if is_tag(oath::Tag::OcraSession) {
+                session = Some(bytes);
+            } else if is_tag(oath::Tag::Timestamp) {
+                let bytes: [u8; 8] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                unix_time = Some(u64::from_be_bytes(bytes));
+            }
+        }
+
+        Ok(CalculateOcra {
+            label,
+            question,
+            response_truncated: true,
+            pin_hash,
+            session,
+            unix_time,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CalculateHashChain<'l> {
+    pub label: &'l [u8],
+    /// Return the hexadecimal form (P2 = 0x01), instead of the six-word indices (P2 = 0x00)
+    pub hex: bool,
+    /// The sequence number of the server challenge. Refused, if it is not the current one.
+    pub sequence: Option<u16>,
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for CalculateHashChain<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        let maybe_sequence: Option<TaggedSlice> =
+            decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        let sequence = match maybe_sequence {
+            Some(slice) => {
+                ensure(
+                    slice.tag() == (oath::Tag::HashChainSequence as u8).try_into().unwrap(),
+                    FAILED_PARSING_ERROR,
+                )?;
+                let bytes: [u8; 2] = slice
+                    .as_bytes()
+                    .try_into()
+                    .map_err(|_| FAILED_PARSING_ERROR)?;
+                Some(u16::from_be_bytes(bytes))
+            }
+            None => None,
+        };
+
+        Ok(CalculateHashChain {
+            label,
+            hex: false,
+            sequence,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct UseRecoveryCode<'l> {
+    pub label: &'l [u8],
+    /// The code to verify. The next unused one is returned, when not set.
+    pub code: Option<&'l [u8]>,
+}
+
+impl core::fmt::Debug for UseRecoveryCode<'_> {
+    fn fmt(
+        &self,
+        fmt: &mut core::fmt::Formatter<'_>,
+    ) -> core::result::Result<(), core::fmt::Error> {
+        fmt.debug_struct("UseRecoveryCode")
+            .field(
+                "label",
+                &core::str::from_utf8(self.label).unwrap_or("invalid UTF8 label"),
+            )
+            .field("code", &self.code.map(|_| "<hidden>"))
+            .finish()
+    }
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for UseRecoveryCode<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        let maybe_code: Option<TaggedSlice> = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        let code = match maybe_code {
+            Some(slice) => {
+                ensure(
+                    slice.tag() == (oath::Tag::RecoveryCode as u8).try_into().unwrap(),
+                    FAILED_PARSING_ERROR,
+                )?;
+                Some(slice.as_bytes())
+            }
+            None => None,
+        };
+
+        Ok(UseRecoveryCode { label, code })
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ListCredentials {
+    /// Extension: follow each entry with its issuer, account and display name (P1 = 0x01)
+    pub details: bool,
+}
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Delete<'l> {
     pub label: &'l [u8],
@@ -356,9 +699,60 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Delete<'l> {
     }
 }
 
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Rename<'l> {
+    pub label: &'l [u8],
+    pub new_label: &'l [u8],
+}
+
+impl core::fmt::Debug for Rename<'_> {
+    fn fmt(
+        &self,
+        fmt: &mut core::fmt::Formatter<'_>,
+    ) -> core::result::Result<(), core::fmt::Error> {
+        fmt.debug_struct("Rename")
+            .field(
+                "label",
+                &core::str::from_utf8(self.label).unwrap_or("invalid UTF8 label"),
+            )
+            .field(
+                "new_label",
+                &core::str::from_utf8(self.new_label).unwrap_or("invalid UTF8 label"),
+            )
+            .finish()
+    }
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for Rename<'l> {
+    type Error = iso7816::Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let label = first.as_bytes();
+
+        let second: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            second.tag() == (oath::Tag::Name as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let new_label = second.as_bytes();
+        ensure(!new_label.is_empty(), FAILED_PARSING_ERROR)?;
+
+        Ok(Rename { label, new_label })
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Register<'l> {
     pub credential: Credential<'l>,
+    /// Generate the secret on the device (P1 = 0x01), instead of taking it from the host
+    pub generate_secret: bool,
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -380,7 +774,24 @@ pub struct Credential<'l> {
     /// Meanwhile, the client app just pads up to 14B :)
     pub secret: &'l [u8],
     pub touch_required: bool,
-    pub counter: Option<u32>,
+    pub counter: Option<u64>,
+    /// Password Safe fields, stored along the OTP secret
+    pub login: Option<&'l [u8]>,
+    pub password: Option<&'l [u8]>,
+    pub metadata: Option<&'l [u8]>,
+    /// Protection class requested by the host. Decided by the device, when not set.
+    pub encryption_key_type: Option<EncryptionKeyType>,
+    pub reverse_hotp_window: Option<u8>,
+    pub period: Option<u32>,
+    pub t0: Option<u64>,
+    pub ocra_suite: Option<&'l [u8]>,
+    pub output_format: Option<oath::OutputFormat>,
+    pub hash_chain_seed: Option<&'l [u8]>,
+    pub recovery_codes: Option<&'l [u8]>,
+    /// Service and account fields, kept apart from the label
+    pub issuer: Option<&'l [u8]>,
+    pub account: Option<&'l [u8]>,
+    pub display_name: Option<&'l [u8]>,
 }
 
 impl core::fmt::Debug for Credential<'_> {
@@ -398,7 +809,62 @@ impl core::fmt::Debug for Credential<'_> {
             .field("digits", &self.digits)
             .field("secret", &hex_str!(&self.secret, 4))
             .field("touch", &self.touch_required)
+            .field("encryption_key_type", &self.encryption_key_type)
             .field("counter", &self.counter)
+            .field("reverse_hotp_window", &self.reverse_hotp_window)
+            .field("period", &self.period)
+            .field("t0", &self.t0)
+            .field("output_format", &self.output_format)
+            .field(
+                "recovery_codes",
+                &self
+                    .recovery_codes
+                    .map(recovery_codes::iter)
+                    .map(Iterator::count),
+            )
+            .field(
+                "hash_chain_seed",
+                &self
+                    .hash_chain_seed
+                    .map(|seed| core::str::from_utf8(seed).unwrap_or("invalid UTF8 seed")),
+            )
+            .field(
+                "ocra_suite",
+                &self
+                    .ocra_suite
+                    .map(|suite| core::str::from_utf8(suite).unwrap_or("invalid UTF8 suite")),
+            )
+            .field(
+                "issuer",
+                &self
+                    .issuer
+                    .map(|issuer| core::str::from_utf8(issuer).unwrap_or("invalid UTF8 issuer")),
+            )
+            .field(
+                "account",
+                &self
+                    .account
+                    .map(|account| core::str::from_utf8(account).unwrap_or("invalid UTF8 account")),
+            )
+            .field(
+                "display_name",
+                &self.display_name.map(|display_name| {
+                    core::str::from_utf8(display_name).unwrap_or("invalid UTF8 display name")
+                }),
+            )
+            .field(
+                "login",
+                &self
+                    .login
+                    .map(|login| core::str::from_utf8(login).unwrap_or("invalid UTF8 login")),
+            )
+            .field("password", &self.password.map(|_| "<hidden>"))
+            .field(
+                "metadata",
+                &self.metadata.map(|metadata| {
+                    core::str::from_utf8(metadata).unwrap_or("invalid UTF8 metadata")
+                }),
+            )
             .finish()
     }
 }
@@ -419,6 +885,21 @@ impl Properties {
     fn touch_required(&self) -> bool {
         self.0 & (oath::Properties::RequireTouch as u8) != 0
     }
+    fn pin_encrypted(&self) -> bool {
+        self.0 & (oath::Properties::PINEncrypt as u8) != 0
+    }
+    fn device_encrypted(&self) -> bool {
+        self.0 & (oath::Properties::DeviceEncrypt as u8) != 0
+    }
+    /// The protection class requested with the extension bits, if any
+    fn encryption_key_type(&self) -> Result<Option<EncryptionKeyType>, Status> {
+        match (self.pin_encrypted(), self.device_encrypted()) {
+            (true, true) => Err(FAILED_PARSING_ERROR),
+            (true, false) => Ok(Some(EncryptionKeyType::PinBased)),
+            (false, true) => Ok(Some(EncryptionKeyType::Hardware)),
+            (false, false) => Ok(None),
+        }
+    }
 }
 impl<'a> flexiber::Decodable<'a> for Properties {
     fn decode(decoder: &mut flexiber::Decoder<'a>) -> flexiber::Result<Properties> {
@@ -441,6 +922,12 @@ impl flexiber::Tagged for Properties {
 impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
     type Error = iso7816::Status;
     fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        Self::parse(data, false)
+    }
+}
+
+impl<'l> Register<'l> {
+    fn parse<const C: usize>(data: &'l Data<C>, generate_secret: bool) -> Result<Self, Status> {
         use flexiber::{Decodable, TagLike};
         type TaggedSlice<'a> = flexiber::TaggedSlice<'a, flexiber::SimpleTag>;
         let mut decoder = flexiber::Decoder::new(data);
@@ -460,7 +947,7 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
             .assert_eq((oath::Tag::Key as u8).try_into().unwrap())
             .map_err(|_| FAILED_PARSING_ERROR)?;
 
-        if second.as_bytes().len() < 3 {
+        if second.as_bytes().len() < 2 {
             return Err(FAILED_PARSING_ERROR);
         };
         let (secret_header, secret) = second.as_bytes().split_at(2);
@@ -468,6 +955,19 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
         let kind: oath::Kind = secret_header[0].try_into()?;
         let algorithm: oath::Algorithm = secret_header[0].try_into()?;
         let digits = secret_header[1];
+        if generate_secret {
+            // the generated secret is returned in the otpauth:// URI, which covers HOTP and TOTP
+            ensure(
+                secret.is_empty() && matches!(kind, oath::Kind::Hotp | oath::Kind::Totp),
+                FAILED_PARSING_ERROR,
+            )?;
+        } else {
+            // the recovery codes have no use for the secret
+            ensure(
+                !secret.is_empty() || kind == oath::Kind::RecoveryCodes,
+                FAILED_PARSING_ERROR,
+            )?;
+        }
 
         let maybe_properties: Option<Properties> =
             decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
@@ -479,6 +979,22 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
                 properties.touch_required()
             })
             .unwrap_or(false);
+        let encryption_key_type = match maybe_properties {
+            Some(properties) => properties.encryption_key_type()?,
+            None => None,
+        };
+        let encryption_key_type = match (kind, encryption_key_type) {
+            // Reverse HOTP and TOTP are used during the boot, before the PIN could be entered,
+            // hence these are always kept under the device-bound key
+            (
+                oath::Kind::HotpReverse | oath::Kind::TotpReverse,
+                Some(EncryptionKeyType::PinBased),
+            ) => return Err(FAILED_PARSING_ERROR),
+            (oath::Kind::HotpReverse | oath::Kind::TotpReverse, _) => {
+                Some(EncryptionKeyType::Hardware)
+            }
+            (_, encryption_key_type) => encryption_key_type,
+        };
 
         let mut counter = None;
         // kind::Hotp and valid u32 starting counter should be more tightly tied together on a
@@ -487,17 +1003,143 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
         if matches!(kind, oath::Kind::Hotp | oath::Kind::HotpReverse) {
             // when the counter is not specified or set to zero, ykman does not send it
             counter = Some(0);
-            if let Ok(last) = TaggedSlice::decode(&mut decoder) {
-                if last.tag() == (oath::Tag::InitialMovingFactor as u8).try_into().unwrap() {
-                    let bytes = last.as_bytes();
-                    if bytes.len() == 4 {
-                        counter = Some(u32::from_be_bytes(bytes.try_into().unwrap()));
-                    }
+        }
+        if kind == oath::Kind::TotpReverse {
+            // the lowest time step to be accepted, raised after each successful verification
+            counter = Some(0);
+        }
+        if kind == oath::Kind::Ocra {
+            // dropped below, if the suite does not use the counter
+            counter = Some(0);
+        }
+        // MD5 is used only by the S/KEY hash chain, which has no use for the HMAC algorithms
+        let algorithm_supported = match kind {
+            oath::Kind::HashChain => {
+                matches!(algorithm, oath::Algorithm::Md5 | oath::Algorithm::Sha1)
+            }
+            _ => algorithm != oath::Algorithm::Md5,
+        };
+        ensure(algorithm_supported, FAILED_PARSING_ERROR)?;
+        let mut login = None;
+        let mut password = None;
+        let mut metadata = None;
+        let mut reverse_hotp_window = None;
+        let mut period = None;
+        let mut t0 = None;
+        let mut ocra_suite = None;
+        let mut output_format = None;
+        let mut hash_chain_seed = None;
+        let mut recovery_codes = None;
+        let mut issuer = None;
+        let mut account = None;
+        let mut display_name = None;
+
+        // the rest of the fields is optional, while a malformed one is refused
+        while !decoder.is_finished() {
+            let tlv = TaggedSlice::decode(&mut decoder).map_err(|_| FAILED_PARSING_ERROR)?;
+            let is_tag = |tag: oath::Tag| tlv.tag() == (tag as u8).try_into().unwrap();
+            let bytes = tlv.as_bytes();
+            if is_tag(oath::Tag::InitialMovingFactor) {
+                // ykman sends a 32-bit value with Tag::InitialMovingFactor, while the 64-bit
+                // counter has to be sent with its own tag
+                if counter.is_some() && bytes.len() == 4 {
+                    counter = Some(u32::from_be_bytes(bytes.try_into().unwrap()).into());
                 }
+            } else if is_tag(oath::Tag::InitialMovingFactor64) {
+                if counter.is_some() {
+                    let bytes: [u8; 8] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                    counter = Some(u64::from_be_bytes(bytes));
+                }
+            } else if is_tag(oath::Tag::PwsLogin) {
+                login = Some(bytes);
+            } else if is_tag(oath::Tag::PwsPassword) {
+                password = Some(bytes);
+            } else if is_tag(oath::Tag::PwsMetadata) {
+                metadata = Some(bytes);
+            } else if is_tag(oath::Tag::ReverseHotpWindow) {
+                ensure(kind == oath::Kind::HotpReverse, FAILED_PARSING_ERROR)?;
+                let [window]: [u8; 1] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                ensure(window <= REVERSE_HOTP_WINDOW_MAX, FAILED_PARSING_ERROR)?;
+                reverse_hotp_window = Some(window);
+            } else if is_tag(oath::Tag::Period) {
+                ensure(kind == oath::Kind::Totp, FAILED_PARSING_ERROR)?;
+                let bytes: [u8; 4] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                let value = u32::from_be_bytes(bytes);
+                ensure(value != 0, FAILED_PARSING_ERROR)?;
+                period = Some(value);
+            } else if is_tag(oath::Tag::T0) {
+                ensure(kind == oath::Kind::Totp, FAILED_PARSING_ERROR)?;
+                let bytes: [u8; 8] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                t0 = Some(u64::from_be_bytes(bytes));
+            } else if is_tag(oath::Tag::OcraSuite) {
+                ensure(kind == oath::Kind::Ocra, FAILED_PARSING_ERROR)?;
+                ocra_suite = Some(bytes);
+            } else if is_tag(oath::Tag::OutputFormat) {
+                // the formatted code is up to 10 characters long
+                ensure(
+                    kind == oath::Kind::Totp && (1..=10).contains(&digits),
+                    FAILED_PARSING_ERROR,
+                )?;
+                let [format]: [u8; 1] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                output_format = Some(format.try_into()?);
+            } else if is_tag(oath::Tag::HashChainSeed) {
+                ensure(
+                    kind == oath::Kind::HashChain && hash_chain::seed_valid(bytes),
+                    FAILED_PARSING_ERROR,
+                )?;
+                hash_chain_seed = Some(bytes);
+            } else if is_tag(oath::Tag::HashChainSequence) {
+                // the sequence number is kept in the counter, and decremented on each use
+                ensure(kind == oath::Kind::HashChain, FAILED_PARSING_ERROR)?;
+                let bytes: [u8; 2] = bytes.try_into().map_err(|_| FAILED_PARSING_ERROR)?;
+                counter = Some(u16::from_be_bytes(bytes).into());
+            } else if is_tag(oath::Tag::RecoveryCodes) {
+                ensure(
+                    kind == oath::Kind::RecoveryCodes && recovery_codes::valid(bytes),
+                    FAILED_PARSING_ERROR,
+                )?;
+                recovery_codes = Some(bytes);
+            } else if is_tag(oath::Tag::Issuer) {
+                issuer = Some(bytes);
+            } else if is_tag(oath::Tag::Account) {
+                account = Some(bytes);
+            } else if is_tag(oath::Tag::DisplayName) {
+                display_name = Some(bytes);
             }
-            debug_now!("counter set to {:?}", &counter);
         }
 
+        if kind == oath::Kind::Ocra {
+            // the suite is required, and has to agree with the algorithm and digits of the header
+            let suite = ocra_suite
+                .and_then(ocra::Suite::parse)
+                .ok_or(FAILED_PARSING_ERROR)?;
+            ensure(
+                suite.algorithm == algorithm && suite.digits == digits,
+                FAILED_PARSING_ERROR,
+            )?;
+            if !suite.counter {
+                counter = None;
+            }
+        }
+        if kind == oath::Kind::HashChain {
+            // the secret is the passphrase here, turned into the passphrase key on the device
+            ensure(
+                hash_chain_seed.is_some()
+                    && counter.map_or(false, |sequence| {
+                        (1..=HASH_CHAIN_SEQUENCE_MAX as u64).contains(&sequence)
+                    })
+                    && (hash_chain::PASSPHRASE_LENGTH_MIN..=hash_chain::PASSPHRASE_LENGTH_MAX)
+                        .contains(&secret.len()),
+                FAILED_PARSING_ERROR,
+            )?;
+        }
+        if kind == oath::Kind::RecoveryCodes {
+            ensure(recovery_codes.is_some(), FAILED_PARSING_ERROR)?;
+        }
+        // T0 has no otpauth:// URI parameter
+        ensure(!generate_secret || t0.is_none(), FAILED_PARSING_ERROR)?;
+        debug_now!("counter set to {:?}", &counter);
+
         let credential = Credential {
             label,
             kind,
@@ -506,9 +1148,78 @@ impl<'l, const C: usize> TryFrom<&'l Data<C>> for Register<'l> {
             secret,
             touch_required,
             counter,
+            login,
+            password,
+            metadata,
+            encryption_key_type,
+            reverse_hotp_window,
+            period,
+            t0,
+            ocra_suite,
+            output_format,
+            hash_chain_seed,
+            recovery_codes,
+            issuer,
+            account,
+            display_name,
+        };
+
+        Ok(Register {
+            credential,
+            generate_secret,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct RegisterUri<'l> {
+    /// The `otpauth://` URI, decoded to the credential on the device
+    pub uri: &'l [u8],
+    pub touch_required: bool,
+    pub encryption_key_type: Option<EncryptionKeyType>,
+}
+
+impl core::fmt::Debug for RegisterUri<'_> {
+    fn fmt(
+        &self,
+        fmt: &mut core::fmt::Formatter<'_>,
+    ) -> core::result::Result<(), core::fmt::Error> {
+        fmt.debug_struct("RegisterUri")
+            .field("uri", &"<hidden>")
+            .field("touch", &self.touch_required)
+            .field("encryption_key_type", &self.encryption_key_type)
+            .finish()
+    }
+}
+
+impl<'l, const C: usize> TryFrom<&'l Data<C>> for RegisterUri<'l> {
+    type Error = Status;
+    fn try_from(data: &'l Data<C>) -> Result<Self, Self::Error> {
+        use flexiber::TaggedSlice;
+        let mut decoder = flexiber::Decoder::new(data);
+
+        let first: TaggedSlice = decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        ensure(
+            first.tag() == (oath::Tag::OtpauthUri as u8).try_into().unwrap(),
+            FAILED_PARSING_ERROR,
+        )?;
+        let uri = first.as_bytes();
+
+        let maybe_properties: Option<Properties> =
+            decoder.decode().map_err(|_| FAILED_PARSING_ERROR)?;
+        let touch_required = maybe_properties
+            .map(|properties| properties.touch_required())
+            .unwrap_or(false);
+        let encryption_key_type = match maybe_properties {
+            Some(properties) => properties.encryption_key_type()?,
+            None => None,
         };
 
-        Ok(Register { credential })
+        Ok(RegisterUri {
+            uri,
+            touch_required,
+            encryption_key_type,
+        })
     }
 }
 
@@ -548,21 +1259,59 @@ impl<'l, const C: usize> TryFrom<&'l iso7816::Command<C>> for Command<'l> {
             let instruction: oath::Instruction = instruction_byte.try_into()?;
             Ok(match (class.into_inner(), instruction, p1, p2) {
                 // also 0xa4
-                (0x00, oath::Instruction::Calculate, 0x00, 0x01) => {
-                    Self::Calculate(Calculate::try_from(data)?)
+                (0x00, oath::Instruction::Calculate, 0x00..=0x01, 0x00..=0x01) => {
+                    Self::Calculate(Calculate {
+                        response_truncated: p2 == 0x01,
+                        unix_time: p1 == 0x01,
+                        ..Calculate::try_from(data)?
+                    })
                 }
                 #[cfg(feature = "calculate-all")]
-                (0x00, oath::Instruction::CalculateAll, 0x00, 0x01) => {
-                    Self::CalculateAll(CalculateAll::try_from(data)?)
+                (0x00, oath::Instruction::CalculateAll, 0x00..=0x01, 0x00..=0x01) => {
+                    Self::CalculateAll(CalculateAll {
+                        response_truncated: p2 == 0x01,
+                        unix_time: p1 == 0x01,
+                        ..CalculateAll::try_from(data)?
+                    })
+                }
+                (0x00, oath::Instruction::CalculateOcra, 0x00, 0x00..=0x01) => {
+                    Self::CalculateOcra(CalculateOcra {
+                        response_truncated: p2 == 0x01,
+                        ..CalculateOcra::try_from(data)?
+                    })
+                }
+                (0x00, oath::Instruction::CalculateHashChain, 0x00, 0x00..=0x01) => {
+                    Self::CalculateHashChain(CalculateHashChain {
+                        hex: p2 == 0x01,
+                        ..CalculateHashChain::try_from(data)?
+                    })
+                }
+                (0x00, oath::Instruction::UseRecoveryCode, 0x00, 0x00) => {
+                    Self::UseRecoveryCode(UseRecoveryCode::try_from(data)?)
                 }
                 (0x00, oath::Instruction::Delete, 0x00, 0x00) => {
                     Self::Delete(Delete::try_from(data)?)
                 }
-                (0x00, oath::Instruction::List, 0x00, 0x00) => Self::ListCredentials,
+                (0x00, oath::Instruction::List, 0x00..=0x01, 0x00) => {
+                    Self::ListCredentials(ListCredentials {
+                        details: p1 == 0x01,
+                    })
+                }
+                (0x00, oath::Instruction::Put, 0x00, 0x00)
+                    if data.first() == Some(&(oath::Tag::OtpauthUri as u8)) =>
+                {
+                    Self::RegisterUri(RegisterUri::try_from(data)?)
+                }
                 (0x00, oath::Instruction::Put, 0x00, 0x00) => {
                     Self::Register(Register::try_from(data)?)
                 }
+                (0x00, oath::Instruction::Put, 0x01, 0x00) => {
+                    Self::Register(Register::parse(data, true)?)
+                }
                 (0x00, oath::Instruction::Reset, 0xde, 0xad) => Self::Reset,
+                (0x00, oath::Instruction::Rename, 0x00, 0x00) => {
+                    Self::Rename(Rename::try_from(data)?)
+                }
                 #[cfg(feature = "challenge-response-auth")]
                 (0x00, oath::Instruction::SetCode, 0x00, 0x00) => {
                     // should check this is a TLV(SetPassword, b'')
@@ -579,6 +1328,12 @@ impl<'l, const C: usize> TryFrom<&'l iso7816::Command<C>> for Command<'l> {
                 (0x00, oath::Instruction::VerifyCode, 0x00, 0x00) => {
                     Self::VerifyCode(VerifyCode::try_from(data)?)
                 }
+                (0x00, oath::Instruction::ResyncCode, 0x00, 0x00) => {
+                    Self::ResyncCode(ResyncCode::try_from(data)?)
+                }
+                (0x00, oath::Instruction::GetCounter, 0x00, 0x00) => {
+                    Self::GetCounter(GetCounter::try_from(data)?)
+                }
                 (0x00, oath::Instruction::VerifyPIN, 0x00, 0x00) => {
                     Self::VerifyPin(VerifyPin::try_from(data)?)
                 }
@@ -588,6 +1343,9 @@ impl<'l, const C: usize> TryFrom<&'l iso7816::Command<C>> for Command<'l> {
                 (0x00, oath::Instruction::SetPIN, 0x00, 0x00) => {
                     Self::SetPin(SetPin::try_from(data)?)
                 }
+                (0x00, oath::Instruction::GetCredential, 0x00, 0x00) => {
+                    Self::GetCredential(GetCredential::try_from(data)?)
+                }
                 (0x00, oath::Instruction::SendRemaining, 0x00, 0x00) => Self::SendRemaining,
                 _ => return Err(Status::InstructionNotSupportedOrInvalid),
             })
@@ -1,4 +1,7 @@
-use crate::{command, oath};
+use crate::{
+    command, hash_chain, oath, recovery_codes, state::EncryptionKeyType,
+    REVERSE_HOTP_WINDOW_DEFAULT, TOTP_DEFAULT_PERIOD,
+};
 use serde::{Deserialize, Serialize};
 use trussed::types::{KeyId, ShortData};
 
@@ -22,17 +25,74 @@ pub struct Credential {
     /// at least 128 bits.  This document RECOMMENDs a shared secret length of 160 bits."
     ///
     /// Meanwhile, the client app just pads up to 14B :)
-
+    ///
+    /// Not stored for the S/KEY hash chain and the recovery codes, which have no use for the key
+    /// in Trussed.
     #[serde(rename = "S")]
-    pub secret: KeyId,
+    pub secret: Option<KeyId>,
     #[serde(rename = "T")]
     pub touch_required: bool,
+    /// HOTP counter. CBOR encodes the integers in their shortest form, regardless of the type,
+    /// so the u32 counters stored before the 64-bit counter support decode as u64 as they are,
+    /// with no migration needed.
     #[serde(rename = "C")]
-    pub counter: Option<u32>,
+    pub counter: Option<u64>,
+
+    /// Password Safe fields. Not present in the credentials stored by the previous versions.
+    #[serde(rename = "L", default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<ShortData>,
+    #[serde(rename = "P", default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<ShortData>,
+    #[serde(rename = "M", default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ShortData>,
+
+    /// Protection class of the credential. Defaults to the PIN-based one, as used by the previous versions.
+    #[serde(rename = "E", default)]
+    pub encryption_key_type: EncryptionKeyType,
+
+    /// Reverse HOTP look-ahead window. The default one is used, when not set.
+    #[serde(rename = "W", default, skip_serializing_if = "Option::is_none")]
+    pub reverse_hotp_window: Option<u8>,
+
+    /// TOTP period in seconds, and T0 as Unix time, used to calculate the time step on the device.
+    /// The defaults of RFC 6238 are used, when not set.
+    #[serde(rename = "I", default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u32>,
+    #[serde(rename = "Z", default, skip_serializing_if = "Option::is_none")]
+    pub t0: Option<u64>,
+
+    /// OCRA suite string, required for the OCRA credentials
+    #[serde(rename = "O", default, skip_serializing_if = "Option::is_none")]
+    pub ocra_suite: Option<ShortData>,
+
+    /// Format of the code calculated on the device. The client gets the truncated digest, when not set.
+    #[serde(rename = "F", default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<oath::OutputFormat>,
+
+    /// S/KEY seed and passphrase key. The latter is kept here, as the hash chain is calculated
+    /// with the plain hash, not available for the keys stored in Trussed.
+    /// The sequence number is kept in the counter.
+    #[serde(rename = "N", default, skip_serializing_if = "Option::is_none")]
+    pub hash_chain_seed: Option<ShortData>,
+    #[serde(rename = "H", default, skip_serializing_if = "Option::is_none")]
+    pub hash_chain_key: Option<hash_chain::Otp>,
+
+    /// Recovery codes not used yet
+    #[serde(rename = "R", default, skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<recovery_codes::List>,
+
+    /// Issuer and account name, and the name to show to the user. Not present in the credentials
+    /// stored by the previous versions, which have these in the label only.
+    #[serde(rename = "V", default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<ShortData>,
+    #[serde(rename = "U", default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<ShortData>,
+    #[serde(rename = "Y", default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<ShortData>,
 }
 
 impl Credential {
-    pub fn try_from(credential: &command::Credential, key: KeyId) -> Result<Self, ()> {
+    pub fn try_from(credential: &command::Credential, key: Option<KeyId>) -> Result<Self, ()> {
         Ok(Self {
             label: ShortData::from_slice(credential.label)?,
             kind: credential.kind,
@@ -41,6 +101,61 @@ impl Credential {
             secret: key,
             touch_required: credential.touch_required,
             counter: credential.counter,
+            login: credential.login.map(ShortData::from_slice).transpose()?,
+            password: credential.password.map(ShortData::from_slice).transpose()?,
+            metadata: credential.metadata.map(ShortData::from_slice).transpose()?,
+            // set by the authenticator before, when not requested by the host
+            encryption_key_type: credential.encryption_key_type.unwrap_or_default(),
+            reverse_hotp_window: credential.reverse_hotp_window,
+            period: credential.period,
+            t0: credential.t0,
+            ocra_suite: credential
+                .ocra_suite
+                .map(ShortData::from_slice)
+                .transpose()?,
+            output_format: credential.output_format,
+            hash_chain_seed: credential
+                .hash_chain_seed
+                .map(ShortData::from_slice)
+                .transpose()?,
+            hash_chain_key: None,
+            recovery_codes: credential
+                .recovery_codes
+                .map(recovery_codes::List::from_slice)
+                .transpose()?,
+            issuer: credential.issuer.map(ShortData::from_slice).transpose()?,
+            account: credential.account.map(ShortData::from_slice).transpose()?,
+            display_name: credential
+                .display_name
+                .map(ShortData::from_slice)
+                .transpose()?,
         })
     }
+
+    /// The TOTP time step for the given Unix time, or None, if it is before T0
+    pub fn totp_time_step(&self, unix_time: u64) -> Option<u64> {
+        let period = self.period.unwrap_or(TOTP_DEFAULT_PERIOD);
+        unix_time
+            .checked_sub(self.t0.unwrap_or(0))
+            .map(|time| time / period as u64)
+    }
+
+    /// The key handle of the HMAC secret, refused for the kinds which have none
+    pub fn key(&self) -> crate::Result<KeyId> {
+        self.secret
+            .ok_or(iso7816::Status::ConditionsOfUseNotSatisfied)
+    }
+
+    /// Number of the recovery codes not used yet
+    pub fn recovery_codes_remaining(&self) -> usize {
+        self.recovery_codes
+            .as_deref()
+            .map_or(0, |list| recovery_codes::iter(list).count())
+    }
+
+    /// The effective Reverse HOTP look-ahead window
+    pub fn reverse_hotp_window(&self) -> u8 {
+        self.reverse_hotp_window
+            .unwrap_or(REVERSE_HOTP_WINDOW_DEFAULT)
+    }
 }
@@ -10,6 +10,7 @@ where
     T: trussed::Client
         + client::HmacSha1
         + client::HmacSha256
+        + client::HmacSha512
         + client::Sha256
         + client::Chacha8Poly1305
         + trussed_auth::AuthClient,
@@ -0,0 +1,90 @@
+//! One-time passwords of the S/KEY hash chain, [RFC 2289][rfc-2289]
+//!
+//! The passphrase key is the folded hash of the seed and passphrase, and the OTP for the sequence
+//! number N is the result of N more hash-and-fold rounds over it.
+//!
+//! [rfc-2289]: https://tools.ietf.org/html/rfc2289
+
+use md5::Md5;
+use sha1::{Digest, Sha1};
+
+use crate::oath;
+
+/// Seed length limits, as in RFC 2289, section 6.0
+pub const SEED_LENGTH_MAX: usize = 16;
+/// Passphrase length limits, as in RFC 2289, section 6.0
+pub const PASSPHRASE_LENGTH_MIN: usize = 10;
+pub const PASSPHRASE_LENGTH_MAX: usize = 63;
+
+/// The 64-bit one-time password, and the passphrase key the chain starts from
+pub type Otp = [u8; 8];
+
+/// Hash the data with the given algorithm, and fold the result to 64 bits
+fn hash_and_fold(algorithm: oath::Algorithm, data: &[u8]) -> Option<Otp> {
+    let mut folded = [0u8; 8];
+    match algorithm {
+        oath::Algorithm::Md5 => {
+            let digest = Md5::digest(data);
+            for (i, byte) in folded.iter_mut().enumerate() {
+                *byte = digest[i] ^ digest[i + 8];
+            }
+        }
+        oath::Algorithm::Sha1 => {
+            // The five 32-bit words are folded to two, and stored little endian,
+            // as in the RFC 2289 Appendix A reference code
+            let digest = Sha1::digest(data);
+            let word = |i: usize| u32::from_be_bytes(digest[4 * i..][..4].try_into().unwrap());
+            let first = word(0) ^ word(2) ^ word(4);
+            let second = word(1) ^ word(3);
+            folded[..4].copy_from_slice(&first.to_le_bytes());
+            folded[4..].copy_from_slice(&second.to_le_bytes());
+        }
+        _ => return None,
+    }
+    Some(folded)
+}
+
+pub fn seed_valid(seed: &[u8]) -> bool {
+    (1..=SEED_LENGTH_MAX).contains(&seed.len()) && seed.iter().all(u8::is_ascii_alphanumeric)
+}
+
+/// The passphrase key, from which the chain starts. The seed is case insensitive.
+pub fn passphrase_key(algorithm: oath::Algorithm, seed: &[u8], passphrase: &[u8]) -> Option<Otp> {
+    let mut data: heapless::Vec<u8, { SEED_LENGTH_MAX + PASSPHRASE_LENGTH_MAX }> =
+        heapless::Vec::new();
+    for c in seed {
+        data.push(c.to_ascii_lowercase()).ok()?;
+    }
+    data.extend_from_slice(passphrase).ok()?;
+    hash_and_fold(algorithm, &data)
+}
+
+/// The OTP for the given sequence number
+pub fn otp(algorithm: oath::Algorithm, passphrase_key: Otp, sequence: u16) -> Option<Otp> {
+    (0..sequence).try_fold(passphrase_key, |otp, _| hash_and_fold(algorithm, &otp))
+}
+
+/// The six-word form of the OTP, as the indices to the RFC 2289 Appendix D dictionary only.
+/// The dictionary itself is not kept on the device, so the client has to look the words up.
+/// The 64 bits are followed by the 2-bit checksum, and split into six 11-bit indices.
+pub fn word_indices(otp: Otp) -> [u16; 6] {
+    let bits = u64::from_be_bytes(otp);
+    let checksum = (0..32).map(|i| (bits >> (2 * i)) & 0b11).sum::<u64>() & 0b11;
+    let bits = ((bits as u128) << 2) | checksum as u128;
+    let mut words = [0u16; 6];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = ((bits >> (11 * (5 - i))) & 0x7ff) as u16;
+    }
+    words
+}
+
+/// The hexadecimal form of the OTP, in upper case
+pub fn hex(otp: Otp) -> [u8; 16] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    let mut hex = [0u8; 16];
+    for (i, byte) in otp.iter().enumerate() {
+        hex[2 * i] = DIGITS[(byte >> 4) as usize];
+        hex[2 * i + 1] = DIGITS[(byte & 0x0f) as usize];
+    }
+    hex
+}
@@ -16,7 +16,11 @@ pub use command::Command;
 mod credential;
 #[cfg(feature = "ctaphid")]
 mod ctaphid;
+mod hash_chain;
 mod oath;
+mod ocra;
+mod otpauth;
+mod recovery_codes;
 mod state;
 
 // https://git.io/JfWuD
@@ -27,9 +31,32 @@ pub const YUBICO_OATH_AID: &[u8] = &hex!("A000000527 2101"); // 01");
 
 /// This constant defines timeout for the regular UP confirmation
 pub const UP_TIMEOUT_MILLISECONDS: u32 = 15 * 1000;
+#[deprecated(note = "replaced by FAILURES_PER_POWER_CYCLE_MAX")]
 pub const FAILURE_FORCED_DELAY_MILLISECONDS: u32 = 1000;
+/// Failed code verifications allowed per power cycle, before these commands are blocked
+pub const FAILURES_PER_POWER_CYCLE_MAX: u8 = 3;
 pub const BACKEND_USER_PIN_ID: u8 = 0;
 pub const ATTEMPT_COUNTER_DEFAULT_RETRIES: u8 = 8;
+/// Failed Reverse HOTP verifications allowed, before the VerifyCode command gets locked
+pub const VERIFY_CODE_DEFAULT_RETRIES: u8 = 8;
+/// Maximum size of the data collected from the chained APDUs
+pub const CHAINING_BUFFER_SIZE: usize = 1024;
+/// Reverse HOTP look-ahead window, used when not set for the credential
+pub const REVERSE_HOTP_WINDOW_DEFAULT: u8 = 9;
+/// Upper bound of the Reverse HOTP look-ahead window, limiting the codes calculated per verification
+pub const REVERSE_HOTP_WINDOW_MAX: u8 = 64;
+/// TOTP period in seconds, used when not set for the credential
+pub const TOTP_DEFAULT_PERIOD: u32 = 30;
+/// Maximum number of the time steps before or after the challenge, calculated in a single call
+pub const TOTP_ADJACENT_TIME_STEPS_MAX: u8 = 5;
+/// Reverse TOTP window, in time steps before and after the one sent by the host
+pub const REVERSE_TOTP_WINDOW: u64 = 1;
+/// Reverse HOTP look-ahead window for the resynchronization with two consecutive codes
+pub const REVERSE_HOTP_RESYNC_WINDOW: u64 = 500;
+/// Upper bound of the S/KEY sequence number, limiting the hash rounds per OTP
+pub const HASH_CHAIN_SEQUENCE_MAX: u16 = 9999;
+/// Maximum size of the recovery codes list, with the length prefix of each code
+pub const RECOVERY_CODES_LENGTH_MAX: usize = 512;
 
 // class AID(bytes, Enum):
 //     OTP = b'\xa0\x00\x00\x05\x27 \x20\x01'
@@ -25,6 +25,59 @@ pub enum Tag {
     Password = 0x80,
     NewPassword = 0x81,
     PINCounter = 0x82,
+    /// Initial value of the HOTP counter, 64-bit unsigned integer, big endian encoded
+    InitialMovingFactor64 = 0x83,
+    /// Password Safe login, stored with the credential
+    PwsLogin = 0x84,
+    /// Password Safe password, stored with the credential
+    PwsPassword = 0x85,
+    /// Password Safe free-form metadata, stored with the credential
+    PwsMetadata = 0x86,
+    /// Reverse HOTP look-ahead window, a single byte
+    ReverseHotpWindow = 0x87,
+    /// Remaining Reverse HOTP verification attempts, a single byte
+    VerifyCodeCounter = 0x88,
+    /// Digits count of the credential, a single byte
+    Digits = 0x89,
+    /// TOTP period in seconds, 32-bit unsigned integer, big endian encoded
+    Period = 0x8a,
+    /// TOTP T0 as Unix time, 64-bit unsigned integer, big endian encoded
+    T0 = 0x8b,
+    /// Number of the time steps before and after the challenge to calculate, two bytes
+    AdjacentTimeSteps = 0x8c,
+    /// OCRA suite string, e.g. "OCRA-1:HOTP-SHA1-6:QN08"
+    OcraSuite = 0x8d,
+    /// OCRA PIN hash, as required by the suite
+    OcraPinHash = 0x8e,
+    /// OCRA session information, as required by the suite
+    OcraSession = 0x8f,
+    /// OCRA timestamp as Unix time, 64-bit unsigned integer, big endian encoded
+    Timestamp = 0x90,
+    /// Output format of the code, a single byte with the `OutputFormat` value
+    OutputFormat = 0x91,
+    /// Tag denotes what follows is (digits, final code string), for the credentials with
+    /// the code formatted on the device
+    FormattedResponse = 0x92,
+    /// S/KEY seed, up to 16 alphanumeric characters
+    HashChainSeed = 0x93,
+    /// S/KEY sequence number, 16-bit unsigned integer, big endian encoded
+    HashChainSequence = 0x94,
+    /// S/KEY one-time password, as the six-word dictionary indices or in the hexadecimal form
+    HashChainOtp = 0x95,
+    /// List of the recovery codes, each one prefixed with its length byte
+    RecoveryCodes = 0x96,
+    /// A single recovery code
+    RecoveryCode = 0x97,
+    /// Number of the recovery codes not used yet, a single byte
+    RecoveryCodesRemaining = 0x98,
+    /// otpauth:// URI of the credential, sent in Put, or returned with the generated secret
+    OtpauthUri = 0x99,
+    /// Issuer, account name and display name of the credential, besides its label
+    Issuer = 0x9a,
+    Account = 0x9b,
+    DisplayName = 0x9c,
+    /// Set in the SELECT answer, when the persistent state is corrupted, and only RESET is accepted
+    StateCorrupted = 0x9d,
 }
 
 #[repr(u8)]
@@ -33,6 +86,8 @@ pub enum Algorithm {
     Sha1 = 0x01,
     Sha256 = 0x02,
     Sha512 = 0x03,
+    /// Only for the S/KEY hash chain, the HMAC calculations do not support it
+    Md5 = 0x04,
 }
 
 impl TryFrom<u8> for Algorithm {
@@ -43,6 +98,7 @@ impl TryFrom<u8> for Algorithm {
             0x1 => Sha1,
             0x2 => Sha256,
             0x3 => Sha512,
+            0x4 => Md5,
             _ => return Err(Self::Error::IncorrectDataParameter),
         })
     }
@@ -54,6 +110,10 @@ pub enum Kind {
     Hotp = 0x10,
     Totp = 0x20,
     HotpReverse = 0x30,
+    TotpReverse = 0x40,
+    Ocra = 0x50,
+    HashChain = 0x60,
+    RecoveryCodes = 0x70,
 }
 
 impl TryFrom<u8> for Kind {
@@ -63,6 +123,28 @@ impl TryFrom<u8> for Kind {
             0x10 => Kind::Hotp,
             0x20 => Kind::Totp,
             0x30 => Kind::HotpReverse,
+            0x40 => Kind::TotpReverse,
+            0x50 => Kind::Ocra,
+            0x60 => Kind::HashChain,
+            0x70 => Kind::RecoveryCodes,
+            _ => return Err(Self::Error::IncorrectDataParameter),
+        })
+    }
+}
+
+/// Format of the code calculated on the device, instead of the decimal one done by the client
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OutputFormat {
+    /// Steam Guard: characters of its 26 letters and digits alphabet
+    Steam = 0x01,
+}
+
+impl TryFrom<u8> for OutputFormat {
+    type Error = iso7816::Status;
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Ok(match byte {
+            0x01 => OutputFormat::Steam,
             _ => return Err(Self::Error::IncorrectDataParameter),
         })
     }
@@ -76,6 +158,10 @@ pub fn combine(kind: Kind, algorithm: Algorithm) -> u8 {
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Properties {
     RequireTouch = 0x02,
+    /// Extension: encrypt the credential with the PIN-based key, instead of the device-bound one
+    PINEncrypt = 0x04,
+    /// Extension: encrypt the credential with the device-bound key, also when the PIN is set
+    DeviceEncrypt = 0x08,
 }
 
 #[repr(u8)]
@@ -85,6 +171,7 @@ pub enum Instruction {
     Delete = 0x02,
     SetCode = 0x03,
     Reset = 0x04,
+    Rename = 0x05,
     List = 0xa1,
     Calculate = 0xa2,
     Validate = 0xa3,
@@ -95,6 +182,12 @@ pub enum Instruction {
     VerifyPIN = 0xb2,
     ChangePIN = 0xb3,
     SetPIN = 0xb4,
+    GetCredential = 0xb5,
+    ResyncCode = 0xb6,
+    GetCounter = 0xb7,
+    CalculateOcra = 0xb8,
+    CalculateHashChain = 0xb9,
+    UseRecoveryCode = 0xba,
 }
 
 impl TryFrom<u8> for Instruction {
@@ -106,6 +199,7 @@ impl TryFrom<u8> for Instruction {
             0x02 => Delete,
             0x03 => SetCode,
             0x04 => Reset,
+            0x05 => Rename,
             0xa1 => List,
             0xa2 => Calculate,
             0xa3 => Validate,
@@ -115,6 +209,12 @@ impl TryFrom<u8> for Instruction {
             0xb2 => VerifyPIN,
             0xb3 => ChangePIN,
             0xb4 => SetPIN,
+            0xb5 => GetCredential,
+            0xb6 => ResyncCode,
+            0xb7 => GetCounter,
+            0xb8 => CalculateOcra,
+            0xb9 => CalculateHashChain,
+            0xba => UseRecoveryCode,
             _ => return Err(Self::Error::InstructionNotSupportedOrInvalid),
         })
     }
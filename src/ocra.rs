@@ -0,0 +1,272 @@
+//! OCRA: OATH Challenge-Response Algorithm, [RFC 6287][rfc-6287]
+//!
+//! Parsing of the OCRA suite string, and the construction of the DataInput message,
+//! which is then signed with HMAC in the same way as the HOTP and TOTP challenges.
+//!
+//! [rfc-6287]: https://tools.ietf.org/html/rfc6287
+
+use iso7816::Status;
+
+use crate::oath;
+use crate::Result;
+
+/// Length of the question field in the DataInput
+const QUESTION_LENGTH: usize = 128;
+/// Maximum length of the session information, as allowed by the Snnn suite parameter
+pub const SESSION_LENGTH_MAX: usize = 512;
+/// Maximum length of the DataInput: suite string, separator, counter, question, PIN hash (SHA512),
+/// session information and timestamp
+pub const DATA_INPUT_LENGTH_MAX: usize =
+    128 + 1 + 8 + QUESTION_LENGTH + 64 + SESSION_LENGTH_MAX + 8;
+
+pub type DataInput = heapless::Vec<u8, DATA_INPUT_LENGTH_MAX>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuestionFormat {
+    /// QA: alphanumeric, used as is
+    Alphanumeric,
+    /// QN: numeric, converted to its hexadecimal representation
+    Numeric,
+    /// QH: hexadecimal
+    Hex,
+}
+
+/// The parsed OCRA suite, e.g. "OCRA-1:HOTP-SHA256-8:C-QN08-PSHA1"
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Suite {
+    pub algorithm: oath::Algorithm,
+    /// Digits of the truncated response. Zero means, that the full HMAC is used instead.
+    pub digits: u8,
+    pub counter: bool,
+    pub question_format: QuestionFormat,
+    pub question_length_max: u8,
+    pub pin_algorithm: Option<oath::Algorithm>,
+    pub session_length: Option<u16>,
+    /// Time step in seconds
+    pub time_step: Option<u64>,
+}
+
+/// Values sent by the host for the DataInput construction
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Inputs<'a> {
+    pub question: &'a [u8],
+    pub pin_hash: Option<&'a [u8]>,
+    pub session: Option<&'a [u8]>,
+    /// Unix time, converted to the time steps of the suite
+    pub unix_time: Option<u64>,
+}
+
+fn parse_algorithm(name: &[u8]) -> Option<oath::Algorithm> {
+    match name {
+        b"SHA1" => Some(oath::Algorithm::Sha1),
+        b"SHA256" => Some(oath::Algorithm::Sha256),
+        b"SHA512" => Some(oath::Algorithm::Sha512),
+        _ => None,
+    }
+}
+
+fn hash_length(algorithm: oath::Algorithm) -> usize {
+    match algorithm {
+        oath::Algorithm::Sha1 => 20,
+        oath::Algorithm::Sha256 => 32,
+        oath::Algorithm::Sha512 => 64,
+        oath::Algorithm::Md5 => 16,
+    }
+}
+
+fn parse_decimal(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 3 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, &digit| {
+        digit
+            .is_ascii_digit()
+            .then(|| value * 10 + (digit - b'0') as u64)
+    })
+}
+
+impl Suite {
+    pub fn parse(suite: &[u8]) -> Option<Self> {
+        let mut parts = suite.split(|&c| c == b':');
+        if parts.next()? != b"OCRA-1" {
+            return None;
+        }
+
+        // CryptoFunction: HOTP-SHAx-t
+        let mut crypto_function = parts.next()?.split(|&c| c == b'-');
+        if crypto_function.next()? != b"HOTP" {
+            return None;
+        }
+        let algorithm = parse_algorithm(crypto_function.next()?)?;
+        let digits = parse_decimal(crypto_function.next()?)? as u8;
+        if !(digits == 0 || (4..=10).contains(&digits)) || crypto_function.next().is_some() {
+            return None;
+        }
+
+        // DataInput: [C] | QFxx | [PH | Snnn | TG], in that order
+        let mut data_input = parts.next()?.split(|&c| c == b'-').peekable();
+        if parts.next().is_some() {
+            return None;
+        }
+
+        let counter = data_input.next_if(|&part| part == b"C").is_some();
+
+        let question = data_input.next()?;
+        if question.len() != 4 || question[0] != b'Q' {
+            return None;
+        }
+        let question_format = match question[1] {
+            b'A' => QuestionFormat::Alphanumeric,
+            b'N' => QuestionFormat::Numeric,
+            b'H' => QuestionFormat::Hex,
+            _ => return None,
+        };
+        let question_length_max = parse_decimal(&question[2..])? as u8;
+        if !(4..=64).contains(&question_length_max) {
+            return None;
+        }
+
+        let pin_algorithm = match data_input.next_if(|part| part.first() == Some(&b'P')) {
+            Some(part) => Some(parse_algorithm(&part[1..])?),
+            None => None,
+        };
+
+        let session_length = match data_input.next_if(|part| part.first() == Some(&b'S')) {
+            Some(part) if part.len() == 4 => Some(parse_decimal(&part[1..])? as u16),
+            Some(_) => return None,
+            None => None,
+        };
+        if session_length.map_or(false, |length| length as usize > SESSION_LENGTH_MAX) {
+            return None;
+        }
+
+        let time_step = match data_input.next_if(|part| part.first() == Some(&b'T')) {
+            Some(part) if part.len() >= 3 => {
+                let (value, unit) = part[1..].split_at(part.len() - 2);
+                let value = parse_decimal(value)?;
+                let (unit_seconds, range) = match unit {
+                    b"S" => (1, 1..=59),
+                    b"M" => (60, 1..=59),
+                    b"H" => (3600, 1..=48),
+                    _ => return None,
+                };
+                if !range.contains(&value) {
+                    return None;
+                }
+                Some(value * unit_seconds)
+            }
+            Some(_) => return None,
+            None => None,
+        };
+
+        if data_input.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            algorithm,
+            digits,
+            counter,
+            question_format,
+            question_length_max,
+            pin_algorithm,
+            session_length,
+            time_step,
+        })
+    }
+
+    /// Build the DataInput message for the HMAC calculation, from the suite string,
+    /// the counter kept by the device, and the values sent by the host.
+    /// Any of the inputs not matching the suite is reported as IncorrectDataParameter.
+    pub fn data_input(
+        &self,
+        suite: &[u8],
+        counter: Option<u64>,
+        inputs: &Inputs<'_>,
+    ) -> Result<DataInput> {
+        let error = Status::IncorrectDataParameter;
+        let mut message = DataInput::new();
+        message.extend_from_slice(suite).map_err(|_| error)?;
+        message.push(0x00).map_err(|_| error)?;
+
+        if self.counter {
+            let counter = counter.ok_or(Status::UnspecifiedPersistentExecutionError)?;
+            message
+                .extend_from_slice(&counter.to_be_bytes())
+                .map_err(|_| error)?;
+        }
+
+        let mut question = [0u8; QUESTION_LENGTH];
+        self.encode_question(inputs.question, &mut question)?;
+        message.extend_from_slice(&question).map_err(|_| error)?;
+
+        if let Some(pin_algorithm) = self.pin_algorithm {
+            let pin_hash = inputs.pin_hash.ok_or(error)?;
+            if pin_hash.len() != hash_length(pin_algorithm) {
+                return Err(error);
+            }
+            message.extend_from_slice(pin_hash).map_err(|_| error)?;
+        }
+
+        if let Some(session_length) = self.session_length {
+            let session = inputs.session.ok_or(error)?;
+            if session.len() != session_length as usize {
+                return Err(error);
+            }
+            message.extend_from_slice(session).map_err(|_| error)?;
+        }
+
+        if let Some(time_step) = self.time_step {
+            let unix_time = inputs.unix_time.ok_or(error)?;
+            message
+                .extend_from_slice(&(unix_time / time_step).to_be_bytes())
+                .map_err(|_| error)?;
+        }
+
+        Ok(message)
+    }
+
+    /// Encode the question into the fixed size field. The numeric and hexadecimal questions
+    /// are written as hexadecimal digits, left aligned and padded with zeros.
+    fn encode_question(&self, question: &[u8], field: &mut [u8; QUESTION_LENGTH]) -> Result {
+        let error = Status::IncorrectDataParameter;
+        // The mutual challenge-response mode concatenates the client and server questions,
+        // hence up to twice the suite's length is accepted
+        if question.len() < 4 || question.len() > 2 * self.question_length_max as usize {
+            return Err(error);
+        }
+
+        match self.question_format {
+            QuestionFormat::Alphanumeric => {
+                if !question.iter().all(u8::is_ascii_alphanumeric) {
+                    return Err(error);
+                }
+                field[..question.len()].copy_from_slice(question);
+            }
+            QuestionFormat::Hex => {
+                for (i, &c) in question.iter().enumerate() {
+                    let nibble = (c as char).to_digit(16).ok_or(error)? as u8;
+                    field[i / 2] |= nibble << (4 * (1 - i % 2));
+                }
+            }
+            QuestionFormat::Numeric => {
+                // Convert the decimal number to binary, big endian. Up to 128 digits fit in 54 bytes.
+                let mut number = [0u8; 54];
+                for &c in question {
+                    let mut carry = (c as char).to_digit(10).ok_or(error)?;
+                    for byte in number.iter_mut().rev() {
+                        let value = *byte as u32 * 10 + carry;
+                        *byte = value as u8;
+                        carry = value >> 8;
+                    }
+                }
+                // Then copy its hexadecimal digits, skipping the leading zeros
+                let nibbles = number.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]);
+                for (i, nibble) in nibbles.skip_while(|&nibble| nibble == 0).enumerate() {
+                    field[i / 2] |= nibble << (4 * (1 - i % 2));
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! The `otpauth://` Key URI format, as used by the authenticator apps for the enrolment
+//!
+//! The secret is encoded with Base32 of [RFC 4648][rfc-4648], with no padding.
+//!
+//! [rfc-4648]: https://tools.ietf.org/html/rfc4648
+
+use core::fmt::Write;
+
+use iso7816::Status;
+use trussed::types::ShortData;
+
+use crate::credential::Credential;
+use crate::{command, ensure, oath, Result, TOTP_DEFAULT_PERIOD};
+
+/// Maximum length of the secret sent in the URI, the SHA512 block size
+pub const SECRET_LENGTH_MAX: usize = 128;
+
+pub type Secret = heapless::Vec<u8, SECRET_LENGTH_MAX>;
+
+/// Maximum length of the label and issuer, as sent in their TLVs
+const LABEL_LENGTH_MAX: usize = 255;
+
+/// Maximum length of the URI: the label and issuer, each percent-encoded in the worst case to
+/// three characters per byte, the Base32 encoded secret, and the rest of the parameters
+pub const URI_LENGTH_MAX: usize = 2 * 3 * LABEL_LENGTH_MAX + (SECRET_LENGTH_MAX * 8 + 4) / 5 + 128;
+
+pub type Uri = heapless::String<URI_LENGTH_MAX>;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Write the data in Base32, with no padding
+fn write_base32(uri: &mut Uri, data: &[u8]) -> Option<()> {
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            uri.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char)
+                .ok()?;
+        }
+    }
+    if bits > 0 {
+        uri.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char)
+            .ok()?;
+    }
+    Some(())
+}
+
+/// Write the label, percent-encoding all but the unreserved characters, and the colon
+/// separating the issuer from the account name
+fn write_label(uri: &mut Uri, label: &[u8]) -> Option<()> {
+    for &c in label {
+        if c.is_ascii_alphanumeric() || b"-._~:".contains(&c) {
+            uri.push(c as char).ok()?;
+        } else {
+            write!(uri, "%{:02X}", c).ok()?;
+        }
+    }
+    Some(())
+}
+
+/// Decode the Base32 data, ignoring the case and the trailing padding
+fn read_base32(data: &[u8], decoded: &mut Secret) -> Result {
+    let padding = data.iter().rev().take_while(|&&c| c == b'=').count();
+    let data = &data[..data.len() - padding];
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for &c in data {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())
+            .ok_or(Status::IncorrectDataParameter)?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded
+                .push((buffer >> bits) as u8)
+                .map_err(|_| Status::IncorrectDataParameter)?;
+        }
+    }
+    Ok(())
+}
+
+/// Decode the percent-encoded label or parameter value
+fn read_percent_encoded(data: &[u8], decoded: &mut ShortData) -> Result {
+    let mut rest = data;
+    while let Some((&c, tail)) = rest.split_first() {
+        let c = match c {
+            b'%' => {
+                let hex = tail.get(..2).ok_or(Status::IncorrectDataParameter)?;
+                rest = &tail[2..];
+                core::str::from_utf8(hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(Status::IncorrectDataParameter)?
+            }
+            c => {
+                rest = tail;
+                c
+            }
+        };
+        decoded
+            .push(c)
+            .map_err(|_| Status::IncorrectDataParameter)?;
+    }
+    Ok(())
+}
+
+fn read_number<T: core::str::FromStr>(data: &[u8]) -> Result<T> {
+    core::str::from_utf8(data)
+        .ok()
+        .filter(|number| number.bytes().all(|c| c.is_ascii_digit()))
+        .and_then(|number| number.parse().ok())
+        .ok_or(Status::IncorrectDataParameter)
+}
+
+fn algorithm_name(algorithm: oath::Algorithm) -> Option<&'static str> {
+    match algorithm {
+        oath::Algorithm::Sha1 => Some("SHA1"),
+        oath::Algorithm::Sha256 => Some("SHA256"),
+        oath::Algorithm::Sha512 => Some("SHA512"),
+        oath::Algorithm::Md5 => None,
+    }
+}
+
+/// The URI for the HOTP or TOTP credential, with the given secret
+pub fn uri(credential: &Credential, secret: &[u8]) -> Option<Uri> {
+    let mut uri = Uri::new();
+    let kind = match credential.kind {
+        oath::Kind::Hotp => "hotp",
+        oath::Kind::Totp => "totp",
+        _ => return None,
+    };
+    write!(uri, "otpauth://{}/", kind).ok()?;
+    write_label(&mut uri, &credential.label)?;
+    uri.push_str("?secret=").ok()?;
+    write_base32(&mut uri, secret)?;
+    write!(
+        uri,
+        "&algorithm={}&digits={}",
+        algorithm_name(credential.algorithm)?,
+        credential.digits
+    )
+    .ok()?;
+    match credential.kind {
+        oath::Kind::Hotp => write!(uri, "&counter={}", credential.counter.unwrap_or(0)).ok()?,
+        _ => write!(
+            uri,
+            "&period={}",
+            credential.period.unwrap_or(TOTP_DEFAULT_PERIOD)
+        )
+        .ok()?,
+    }
+    if let Some(issuer) = &credential.issuer {
+        uri.push_str("&issuer=").ok()?;
+        write_label(&mut uri, issuer)?;
+    }
+    Some(uri)
+}
+
+/// Decode the URI of a HOTP or TOTP credential. The label, issuer and secret are decoded to the
+/// given buffers. Malformed URIs are refused with `IncorrectDataParameter`, and the parameters the
+/// device cannot use with `FunctionNotSupported`.
+///
+/// The issuer is taken from its parameter, or else from the label prefix, and the account name
+/// is the rest of the label.
+pub fn parse<'a>(
+    uri: &[u8],
+    label: &'a mut ShortData,
+    issuer: &'a mut ShortData,
+    secret: &'a mut Secret,
+) -> Result<command::Credential<'a>> {
+    let rest = uri
+        .strip_prefix(b"otpauth://")
+        .ok_or(Status::IncorrectDataParameter)?;
+    let (kind, rest) = if let Some(rest) = rest.strip_prefix(b"hotp/") {
+        (oath::Kind::Hotp, rest)
+    } else if let Some(rest) = rest.strip_prefix(b"totp/") {
+        (oath::Kind::Totp, rest)
+    } else {
+        return Err(Status::FunctionNotSupported);
+    };
+    let separator = rest
+        .iter()
+        .position(|&c| c == b'?')
+        .ok_or(Status::IncorrectDataParameter)?;
+    let (path, query) = (&rest[..separator], &rest[separator + 1..]);
+    read_percent_encoded(path, label)?;
+    ensure(!label.is_empty(), Status::IncorrectDataParameter)?;
+
+    let mut algorithm = oath::Algorithm::Sha1;
+    let mut digits = 6;
+    let mut counter = None;
+    let mut period = None;
+    for parameter in query.split(|&c| c == b'&') {
+        let separator = parameter
+            .iter()
+            .position(|&c| c == b'=')
+            .ok_or(Status::IncorrectDataParameter)?;
+        let (name, value) = (&parameter[..separator], &parameter[separator + 1..]);
+        match name {
+            b"secret" => {
+                secret.clear();
+                read_base32(value, secret)?;
+            }
+            b"algorithm" => {
+                algorithm = [
+                    oath::Algorithm::Sha1,
+                    oath::Algorithm::Sha256,
+                    oath::Algorithm::Sha512,
+                ]
+                .into_iter()
+                .find(|&algorithm| {
+                    algorithm_name(algorithm)
+                        .map_or(false, |name| name.as_bytes().eq_ignore_ascii_case(value))
+                })
+                .ok_or(Status::FunctionNotSupported)?;
+            }
+            b"digits" => digits = read_number(value)?,
+            b"counter" => {
+                ensure(kind == oath::Kind::Hotp, Status::FunctionNotSupported)?;
+                counter = Some(read_number(value)?);
+            }
+            b"period" => {
+                ensure(kind == oath::Kind::Totp, Status::FunctionNotSupported)?;
+                let value: u32 = read_number(value)?;
+                ensure(value != 0, Status::FunctionNotSupported)?;
+                period = Some(value);
+            }
+            b"issuer" => {
+                issuer.clear();
+                read_percent_encoded(value, issuer)?;
+            }
+            // the image and other parameters are not used by the device
+            _ => {}
+        }
+    }
+    ensure(!secret.is_empty(), Status::IncorrectDataParameter)?;
+    // 6 and 8 digits are the common ones, while 7 is allowed by RFC 4226 too
+    ensure((6..=8).contains(&digits), Status::FunctionNotSupported)?;
+    if kind == oath::Kind::Hotp {
+        // when not sent, the counter starts from zero, as with ykman
+        counter = Some(counter.unwrap_or(0));
+    }
+
+    let label: &'a [u8] = label;
+    let issuer: &'a [u8] = issuer;
+    let (label_issuer, account) = match label.iter().position(|&c| c == b':') {
+        Some(separator) => (Some(&label[..separator]), &label[separator + 1..]),
+        None => (None, label),
+    };
+    // the account name may follow the colon after spaces
+    let account_start = account.iter().take_while(|&&c| c == b' ').count();
+    let account = &account[account_start..];
+    let issuer = Some(issuer)
+        .filter(|issuer| !issuer.is_empty())
+        .or(label_issuer)
+        .filter(|issuer| !issuer.is_empty());
+
+    Ok(command::Credential {
+        label,
+        kind,
+        algorithm,
+        digits,
+        secret,
+        touch_required: false,
+        counter,
+        login: None,
+        password: None,
+        metadata: None,
+        encryption_key_type: None,
+        reverse_hotp_window: None,
+        period,
+        t0: None,
+        ocra_suite: None,
+        output_format: None,
+        hash_chain_seed: None,
+        recovery_codes: None,
+        issuer,
+        account: Some(account).filter(|account| !account.is_empty()),
+        display_name: None,
+    })
+}
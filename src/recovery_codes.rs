@@ -0,0 +1,80 @@
+//! Lists of the one-time recovery codes
+//!
+//! The codes are kept one after another, each prefixed with its length byte, in the same way
+//! as these are sent in the Put command. The used codes are removed from the list.
+
+use heapless_bytes::Bytes;
+
+use crate::RECOVERY_CODES_LENGTH_MAX;
+
+/// Length limits of a single recovery code
+pub const CODE_LENGTH_MIN: usize = 4;
+pub const CODE_LENGTH_MAX: usize = 64;
+
+pub type List = Bytes<RECOVERY_CODES_LENGTH_MAX>;
+
+/// Split the first code off the list, if the list is well-formed
+fn split_first_code(list: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&length, rest) = list.split_first()?;
+    let length = length as usize;
+    if !(CODE_LENGTH_MIN..=CODE_LENGTH_MAX).contains(&length) || length > rest.len() {
+        return None;
+    }
+    Some(rest.split_at(length))
+}
+
+/// Iterate over the codes, stopping at the first malformed one
+pub fn iter(list: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = list;
+    core::iter::from_fn(move || {
+        let (code, next) = split_first_code(rest)?;
+        rest = next;
+        Some(code)
+    })
+}
+
+/// Check the list sent by the host: at least one code, and all of them well-formed
+pub fn valid(list: &[u8]) -> bool {
+    let mut rest = list;
+    while !rest.is_empty() {
+        match split_first_code(rest) {
+            Some((_, next)) => rest = next,
+            None => return false,
+        }
+    }
+    !list.is_empty() && list.len() <= RECOVERY_CODES_LENGTH_MAX
+}
+
+/// Compare the codes without an early exit on the first differing byte
+fn equal(code: &[u8], other: &[u8]) -> bool {
+    code.len() == other.len()
+        && code
+            .iter()
+            .zip(other)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Index of the given code in the list. All codes are compared, each in constant time,
+/// so the timing does not reveal which part of the code matched.
+pub fn position(list: &[u8], code: &[u8]) -> Option<usize> {
+    iter(list).enumerate().fold(None, |found, (index, other)| {
+        // compared before the match, so it is not skipped after the code is found
+        let matched = equal(other, code);
+        match found {
+            None if matched => Some(index),
+            found => found,
+        }
+    })
+}
+
+/// The list without the code at the given index
+pub fn without(list: &[u8], index: usize) -> List {
+    let mut remaining = List::new();
+    for (_, code) in iter(list).enumerate().filter(|(i, _)| *i != index) {
+        // the list only gets shorter, so it always fits
+        remaining.push(code.len() as u8).ok();
+        remaining.extend_from_slice(code).ok();
+    }
+    remaining
+}
@@ -4,10 +4,11 @@ use iso7816::Status;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
+use crate::{CHAINING_BUFFER_SIZE, VERIFY_CODE_DEFAULT_RETRIES};
 use encrypted_container::EncryptedDataContainer;
 use trussed::types::Message;
 use trussed::{
-    cbor_deserialize, syscall, try_syscall,
+    cbor_deserialize, cbor_serialize, try_syscall,
     types::{KeyId, Location, PathBuf},
 };
 
@@ -36,6 +37,27 @@ pub struct Persistent {
     /// It is used for authorization using challenge HMAC-SHA1'ing.
     #[cfg(feature = "challenge-response-auth")]
     pub authorization_key: Option<KeyId>,
+    /// Device-bound key for the credentials, which do not require PIN for their use.
+    /// Created on the first use, hence missing in the state written by the previous versions.
+    #[serde(default)]
+    pub encryption_key_hardware: Option<KeyId>,
+    /// Failed Reverse HOTP verifications since the last successful one, shared by all credentials
+    #[serde(default)]
+    pub verify_code_failures: u8,
+}
+
+/// The key used to encrypt the credential at rest.
+///
+/// Note: the default is meant for the credentials stored by the previous versions, which are
+/// all PIN-based. New credentials get the class requested in the Put command, and otherwise
+/// the PIN-based one once the PIN is set, or the device-bound one before that.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum EncryptionKeyType {
+    /// Device-bound key, available without the PIN
+    Hardware,
+    /// Key derived from the PIN, available after its verification
+    #[default]
+    PinBased,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
@@ -55,6 +77,18 @@ pub struct Runtime {
 
     /// Cache
     pub encryption_key: Option<KeyId>,
+    /// Cache of the Persistent::encryption_key_hardware
+    pub encryption_key_hardware: Option<KeyId>,
+
+    /// Command assembled from the chained APDUs received so far.
+    /// Cleared once the last APDU of the chain arrives, or when another command interrupts it.
+    pub chained_command: Option<iso7816::Command<CHAINING_BUFFER_SIZE>>,
+
+    /// Set once the persistent state was found to be readable, or was created on the first run
+    pub persistent_state_checked: bool,
+
+    /// Failed code verifications in a row in this power cycle
+    pub failures: u8,
 }
 
 impl Runtime {
@@ -63,7 +97,17 @@ impl Runtime {
     }
 }
 
-impl Persistent {}
+impl Persistent {
+    /// Remaining Reverse HOTP verification attempts
+    pub fn verify_code_retries(&self) -> u8 {
+        VERIFY_CODE_DEFAULT_RETRIES.saturating_sub(self.verify_code_failures)
+    }
+
+    #[cfg(feature = "challenge-response-auth")]
+    pub fn password_set(&self) -> bool {
+        self.authorization_key.is_some()
+    }
+}
 
 impl State {
     const FILENAME: &'static str = "state.bin";
@@ -84,14 +128,18 @@ impl State {
         trussed: &mut T,
         filename: PathBuf,
         obj: &O,
+        encryption_key_type: EncryptionKeyType,
     ) -> crate::Result
     where
         T: trussed::Client + trussed::client::Chacha8Poly1305,
         O: Serialize,
     {
-        let encryption_key = self
-            .get_encryption_key_from_state()
-            .map_err(|_| iso7816::Status::SecurityStatusNotSatisfied)?;
+        let encryption_key = match encryption_key_type {
+            EncryptionKeyType::Hardware => self.get_encryption_key_hardware(trussed)?,
+            EncryptionKeyType::PinBased => self
+                .get_encryption_key_from_state()
+                .map_err(|_| iso7816::Status::SecurityStatusNotSatisfied)?,
+        };
         let data = EncryptedDataContainer::from_obj(trussed, obj, None, encryption_key)
             .map_err(|_| Status::UnspecifiedPersistentExecutionError)?;
         let data_serialized: Message = data
@@ -115,6 +163,41 @@ impl State {
         self.runtime.encryption_key.ok_or(trussed::Error::NoSuchKey)
     }
 
+    /// Get the device-bound encryption key, if it was already created
+    fn load_encryption_key_hardware<T>(&mut self, trussed: &mut T) -> crate::Result<Option<KeyId>>
+    where
+        T: trussed::Client + trussed::client::Chacha8Poly1305,
+    {
+        if self.runtime.encryption_key_hardware.is_none() {
+            self.runtime.encryption_key_hardware =
+                self.with_persistent(trussed, |_, state| state.encryption_key_hardware)?;
+        }
+        Ok(self.runtime.encryption_key_hardware)
+    }
+
+    /// Get the device-bound encryption key, generating it on the first use
+    fn get_encryption_key_hardware<T>(&mut self, trussed: &mut T) -> crate::Result<KeyId>
+    where
+        T: trussed::Client + trussed::client::Chacha8Poly1305,
+    {
+        if let Some(key) = self.load_encryption_key_hardware(trussed)? {
+            return Ok(key);
+        }
+
+        let location = self.location;
+        let key = self.try_with_persistent_mut(trussed, |trussed, state| {
+            let key = try_syscall!(trussed.generate_chacha8poly1305_key(location))
+                .map_err(|_| Status::NotEnoughMemory)?
+                .key;
+            state.encryption_key_hardware = Some(key);
+            Ok(key)
+        })?;
+        self.runtime.encryption_key_hardware = Some(key);
+        Ok(key)
+    }
+
+    /// Decrypt the content with the PIN-based key, if the PIN was verified,
+    /// and with the device-bound key otherwise, or if that fails.
     pub fn decrypt_content<T, O>(
         &mut self,
         trussed: &mut T,
@@ -124,11 +207,20 @@ impl State {
         T: trussed::Client + trussed::client::Chacha8Poly1305,
         O: DeserializeOwned,
     {
-        let encryption_key = self
-            .get_encryption_key_from_state()
-            .map_err(|_| encrypted_container::Error::FailedDecryption)?;
+        let container = EncryptedDataContainer::try_from(ser_encrypted.as_slice())?;
 
-        EncryptedDataContainer::decrypt_from_bytes(trussed, ser_encrypted, encryption_key)
+        if let Some(encryption_key) = self.runtime.encryption_key {
+            if let Ok(obj) = container.decrypt(trussed, None, encryption_key) {
+                return Ok(obj);
+            }
+        }
+
+        let encryption_key = self
+            .load_encryption_key_hardware(trussed)
+            .ok()
+            .flatten()
+            .ok_or(encrypted_container::Error::FailedDecryption)?;
+        container.decrypt(trussed, None, encryption_key)
     }
 
     pub fn try_read_file<T, O>(
@@ -152,11 +244,11 @@ impl State {
         &mut self,
         trussed: &mut T,
         f: impl FnOnce(&mut T, &Persistent) -> X,
-    ) -> X
+    ) -> crate::Result<X>
     where
         T: trussed::Client + trussed::client::Chacha8Poly1305,
     {
-        let state = self.get_persistent_or_default(trussed);
+        let state = self.get_persistent(trussed)?;
 
         #[cfg(feature = "devel-counters")]
         {
@@ -165,33 +257,120 @@ impl State {
         }
         // 2. Let the app read the state
 
-        f(trussed, &state)
+        Ok(f(trussed, &state))
     }
 
-    fn get_persistent_or_default(&self, trussed: &mut impl trussed::Client) -> Persistent {
-        // 1. If there is serialized, persistent state (i.e., the try_syscall! to `read_file` does
-        //    not fail), then assume it is valid and deserialize it. If the reading fails, assume
-        //    that this is the first run, and set defaults.
-        //
-        // NB: This is an attack vector. If the state can be corrupted, this clears the password.
-        // Consider resetting the device in this situation
-        // TODO DESIGN discuss, should failed deserialization be reacted on differently
-        // TODO handle error from getting the random bytes
-        try_syscall!(trussed.read_file(self.location, PathBuf::from(Self::FILENAME)))
-            .ok()
-            .and_then(|response| cbor_deserialize(&response.data).ok())
-            .unwrap_or_else(|| {
-                let salt: [u8; 8] = syscall!(trussed.random_bytes(8))
-                    .bytes
-                    .as_ref()
-                    .try_into()
-                    .unwrap();
-                Persistent { salt }
-            })
+    /// Let the app modify the persistent state, and write it back to the storage.
+    /// Nothing is written, if the closure returns an error.
+    pub fn try_with_persistent_mut<T, X>(
+        &mut self,
+        trussed: &mut T,
+        f: impl FnOnce(&mut T, &mut Persistent) -> crate::Result<X>,
+    ) -> crate::Result<X>
+    where
+        T: trussed::Client + trussed::client::Chacha8Poly1305,
+    {
+        let mut state = self.get_persistent(trussed)?;
+
+        #[cfg(feature = "devel-counters")]
+        {
+            self.counter_read_write += 1;
+            debug_now!("Getting the state RW {}", self.counter_read_write);
+        }
+
+        let x = f(trussed, &mut state)?;
+        self.write_persistent(trussed, &state)?;
+        Ok(x)
+    }
+
+    fn write_persistent(
+        &self,
+        trussed: &mut impl trussed::Client,
+        state: &Persistent,
+    ) -> crate::Result {
+        let data = Message::try_from(|buf| {
+            cbor_serialize(state, buf)
+                .map(|s| s.len())
+                .map_err(|_| Status::UnspecifiedPersistentExecutionError)
+        })?;
+        // The file content is committed by littlefs at once, when the file is closed,
+        // so the state is either fully updated, or left intact on a power loss.
+        try_syscall!(trussed.write_file(self.location, PathBuf::from(Self::FILENAME), data, None))
+            .map_err(|_| Status::NotEnoughMemory)?;
+        Ok(())
+    }
+
+    /// Make sure the persistent state is usable, creating it on the first run.
+    /// Checked once per power cycle, unless the state is found to be corrupted.
+    pub fn check_persistent<T>(&mut self, trussed: &mut T) -> crate::Result
+    where
+        T: trussed::Client + trussed::client::Chacha8Poly1305,
+    {
+        if !self.runtime.persistent_state_checked {
+            self.get_persistent(trussed)?;
+            self.runtime.persistent_state_checked = true;
+        }
+        Ok(())
+    }
+
+    fn get_persistent(&self, trussed: &mut impl trussed::Client) -> crate::Result<Persistent> {
+        // 1. If there is no serialized, persistent state (i.e., the file does not exist),
+        //    assume that this is the first run, and create it with the defaults.
+        //    It has to be written right away, so the salt used as the device ID stays stable.
+        // 2. If the state is there, but can't be read or deserialized, report an error instead of
+        //    silently resetting it, since that would clear the password, rotate the salt, and
+        //    lose the device-bound key. The device has to be reset by the user in that case.
+        fn corrupted<E>(_: E) -> Status {
+            error_now!("Persistent state is corrupted. Reset the device to continue.");
+            Status::UnspecifiedPersistentExecutionError
+        }
+        let metadata =
+            try_syscall!(trussed.entry_metadata(self.location, PathBuf::from(Self::FILENAME)))
+                .map_err(corrupted)?
+                .metadata;
+        if metadata.is_none() {
+            return self.create_persistent(trussed);
+        }
+        let data = try_syscall!(trussed.read_file(self.location, PathBuf::from(Self::FILENAME)))
+            .map_err(corrupted)?
+            .data;
+        cbor_deserialize(&data).map_err(corrupted)
+    }
+
+    fn create_persistent(&self, trussed: &mut impl trussed::Client) -> crate::Result<Persistent> {
+        let salt: [u8; 8] = try_syscall!(trussed.random_bytes(8))
+            .map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?
+            .bytes
+            .as_ref()
+            .try_into()
+            .map_err(|_| Status::UnspecifiedNonpersistentExecutionError)?;
+        let state = Persistent {
+            salt,
+            #[cfg(feature = "challenge-response-auth")]
+            authorization_key: None,
+            encryption_key_hardware: None,
+            verify_code_failures: 0,
+        };
+        self.write_persistent(trussed, &state)?;
+        debug_now!("Created the persistent state");
+        Ok(state)
     }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum CommandState {
-    ListCredentials(usize),
+    ListCredentials {
+        file_index: usize,
+        /// Tells, if the PIN-protected credentials are to be listed as well
+        pin_authorized: bool,
+        details: bool,
+    },
+    #[cfg(feature = "calculate-all")]
+    CalculateAll {
+        file_index: usize,
+        pin_authorized: bool,
+        challenge: heapless::Vec<u8, 8>,
+        response_truncated: bool,
+        unix_time: bool,
+    },
 }
@@ -0,0 +1,156 @@
+mod common;
+
+use common::{apdu, respond, send, tlv, unlock, with_authenticator, VirtClient};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+
+/// RFC 4226 test secret
+const SECRET: &[u8] = b"12345678901234567890";
+
+/// Registers the credential with the given YKOATH properties byte
+fn register_with_properties(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    kind_algorithm: u8,
+    properties: u8,
+) {
+    let mut key = vec![kind_algorithm, 6];
+    key.extend_from_slice(SECRET);
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x73, &key));
+    data.extend([0x78, properties]);
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+fn calculate(authenticator: &mut Authenticator<VirtClient>, label: &[u8]) -> Result<(), Status> {
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x74, &1u64.to_be_bytes()));
+    send(authenticator, &apdu(0xa2, 0x00, 0x01, &data)).map(drop)
+}
+
+#[test]
+fn pin_based_credential_requires_pin() {
+    with_authenticator(|authenticator| {
+        // TOTP | SHA1, with the PIN-based key requested explicitly
+        register_with_properties(authenticator, b"pin-based", 0x21, 0x04);
+
+        assert_eq!(
+            calculate(authenticator, b"pin-based"),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+        unlock(authenticator);
+        calculate(authenticator, b"pin-based").unwrap();
+    });
+}
+
+#[test]
+fn credential_defaults_to_pin_once_pin_is_set() {
+    with_authenticator(|authenticator| {
+        // no properties, as sent by the stock clients
+        common::register(authenticator, b"default", 0x21, 6, SECRET);
+
+        assert_eq!(
+            calculate(authenticator, b"default"),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn hardware_credential_works_without_pin() {
+    with_authenticator(|authenticator| {
+        register_with_properties(authenticator, b"hardware", 0x21, 0x08);
+
+        calculate(authenticator, b"hardware").unwrap();
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert_eq!(&list[..], b"\x72\x09\x21hardware");
+
+        // Both bits at once are refused
+        let mut key = vec![0x21, 6];
+        key.extend_from_slice(SECRET);
+        let mut data = tlv(0x71, b"both");
+        data.extend(tlv(0x73, &key));
+        data.extend([0x78, 0x0c]);
+        unlock(authenticator);
+        assert_eq!(
+            send(authenticator, &apdu(0x01, 0x00, 0x00, &data)),
+            Err(Status::IncorrectDataParameter)
+        );
+    });
+}
+
+#[test]
+fn modification_requires_pin_once_set() {
+    with_authenticator(|authenticator| {
+        register_with_properties(authenticator, b"hardware", 0x21, 0x08);
+
+        let delete = apdu(0x02, 0x00, 0x00, &tlv(0x71, b"hardware"));
+        assert_eq!(
+            send(authenticator, &delete),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+        let mut data = tlv(0x71, b"hardware");
+        data.extend(tlv(0x71, b"renamed"));
+        assert_eq!(
+            send(authenticator, &apdu(0x05, 0x00, 0x00, &data)),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+        // The Password Safe fields are not returned without the PIN either
+        assert_eq!(
+            send(
+                authenticator,
+                &apdu(0xb5, 0x00, 0x00, &tlv(0x71, b"hardware"))
+            ),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+        calculate(authenticator, b"hardware").unwrap();
+
+        unlock(authenticator);
+        send(authenticator, &delete).unwrap();
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn reverse_hotp_credential_cant_be_pin_based() {
+    with_authenticator(|authenticator| {
+        let mut key = vec![0x31, 6];
+        key.extend_from_slice(SECRET);
+        let mut data = tlv(0x71, b"reverse-pin");
+        data.extend(tlv(0x73, &key));
+        data.extend([0x78, 0x04]);
+        unlock(authenticator);
+        assert_eq!(
+            send(authenticator, &apdu(0x01, 0x00, 0x00, &data)),
+            Err(Status::IncorrectDataParameter)
+        );
+    });
+}
+
+#[test]
+fn interrupted_listing_is_not_continued_after_another_command() {
+    with_authenticator(|authenticator| {
+        // Each entry takes 65 bytes, so not all of them fit in a single response
+        for i in 0..50 {
+            let label = format!("{:02}-{}", i, "x".repeat(59));
+            register_with_properties(authenticator, label.as_bytes(), 0x21, 0x04);
+        }
+
+        unlock(authenticator);
+        let list = apdu(0xa1, 0x00, 0x00, &[]);
+        let (response, status) = respond(authenticator, &list);
+        assert_eq!(status, Err(Status::MoreAvailable(0xff)));
+        assert!(!response.is_empty());
+
+        assert_eq!(calculate(authenticator, b"00"), Err(Status::NotFound));
+        assert_eq!(
+            send(authenticator, &apdu(0xa5, 0x00, 0x00, &[])),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+    });
+}
@@ -0,0 +1,115 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use common::{apdu, register, respond, select, send, tlv, unlock, with_authenticator, VirtClient};
+use hmac::{Hmac, Mac};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+use sha1::Sha1;
+
+/// RFC 6238 test secret for HMAC-SHA1
+const SECRET: &[u8] = b"12345678901234567890";
+
+/// Response of a single credential: the tag, the digits and the (truncated) digest
+type Entry = (u8, u8, Vec<u8>);
+
+/// Splits the CALCULATE ALL response into the entries of the credentials, by their labels
+fn entries(mut data: &[u8]) -> BTreeMap<Vec<u8>, Entry> {
+    let mut entries = BTreeMap::new();
+    while !data.is_empty() {
+        assert_eq!(data[0], 0x71);
+        let (label, rest) = data[2..].split_at(data[1] as usize);
+        let (tag, length) = (rest[0], rest[1] as usize);
+        let (value, rest) = rest[2..].split_at(length);
+        entries.insert(label.to_vec(), (tag, value[0], value[1..].to_vec()));
+        data = rest;
+    }
+    entries
+}
+
+fn calculate_all(
+    authenticator: &mut Authenticator<VirtClient>,
+    p2: u8,
+) -> (Vec<u8>, Result<(), Status>) {
+    unlock(authenticator);
+    let challenge = tlv(0x74, &1u64.to_be_bytes());
+    respond(authenticator, &apdu(0xa4, 0x00, p2, &challenge))
+}
+
+#[test]
+fn touch_and_hotp_markers() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // TOTP | SHA1, RFC 6238 test vector for T = 59
+        register(authenticator, b"totp", 0x21, 8, SECRET);
+        register(authenticator, b"hotp", 0x11, 6, SECRET);
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+        let mut key = vec![0x21, 6];
+        key.extend_from_slice(SECRET);
+        let mut data = tlv(0x71, b"touch");
+        data.extend(tlv(0x73, &key));
+        data.extend([0x78, 0x02]);
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        let (response, status) = calculate_all(authenticator, 0x01);
+        status.unwrap();
+        let entries = entries(&response);
+        // The Reverse HOTP credential is skipped
+        assert_eq!(entries.len(), 3);
+        let (tag, digits, truncated) = &entries[&b"totp"[..]];
+        assert_eq!((*tag, *digits), (0x76, 8));
+        let truncated = u32::from_be_bytes(truncated[..].try_into().unwrap());
+        assert_eq!((truncated & 0x7FFF_FFFF) % 10u32.pow(8), 94287082);
+        // No codes are calculated for HOTP and for the credentials requiring touch
+        assert_eq!(entries[&b"hotp"[..]], (0x77, 6, vec![]));
+        assert_eq!(entries[&b"touch"[..]], (0x7c, 6, vec![]));
+    });
+}
+
+#[test]
+fn full_response() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"totp", 0x21, 8, SECRET);
+
+        let (response, status) = calculate_all(authenticator, 0x00);
+        status.unwrap();
+        let mut mac = Hmac::<Sha1>::new_from_slice(SECRET).unwrap();
+        mac.update(&1u64.to_be_bytes());
+        assert_eq!(
+            entries(&response)[&b"totp"[..]],
+            (0x75, 8, mac.finalize().into_bytes().to_vec())
+        );
+    });
+}
+
+#[test]
+fn continuation_through_send_remaining() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // Each entry takes 71 bytes, so not all of them fit in a single response
+        let labels: Vec<Vec<u8>> = (0..50)
+            .map(|i| format!("{:02}-{}", i, "x".repeat(60)).into_bytes())
+            .collect();
+        for label in &labels {
+            register(authenticator, label, 0x21, 8, SECRET);
+        }
+
+        let (mut response, mut status) = calculate_all(authenticator, 0x01);
+        let mut parts = 1;
+        while status == Err(Status::MoreAvailable(0xff)) {
+            let (more, more_status) = respond(authenticator, &apdu(0xa5, 0x00, 0x00, &[]));
+            response.extend(more);
+            status = more_status;
+            parts += 1;
+        }
+        status.unwrap();
+        assert!(parts > 1);
+
+        let entries = entries(&response);
+        assert_eq!(entries.keys().cloned().collect::<Vec<_>>(), labels);
+        assert!(entries.values().all(|(tag, _, _)| *tag == 0x76));
+    });
+}
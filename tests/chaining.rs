@@ -0,0 +1,88 @@
+mod common;
+
+use common::{apdu, calculate_code, send, tlv, unlock, with_authenticator};
+use iso7816::Status;
+
+/// Sends the data with the given instruction in chained APDUs, each carrying up to `chunk` bytes
+fn send_chained(
+    authenticator: &mut oath_authenticator::Authenticator<common::VirtClient>,
+    ins: u8,
+    data: &[u8],
+    chunk: usize,
+) -> Result<heapless::Vec<u8, { 3 * 1024 }>, Status> {
+    let mut chunks = data.chunks(chunk).peekable();
+    while let Some(part) = chunks.next() {
+        let mut command = apdu(ins, 0x00, 0x00, part);
+        if chunks.peek().is_some() {
+            command[0] |= 0x10;
+            send(authenticator, &command)?;
+        } else {
+            return send(authenticator, &command);
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn chained_put() {
+    with_authenticator(|authenticator| {
+        // TOTP | SHA1, RFC 6238 test vector for T = 59
+        let mut key = vec![0x21, 8];
+        key.extend_from_slice(b"12345678901234567890");
+        let mut data = tlv(0x71, b"chained");
+        data.extend(tlv(0x73, &key));
+
+        // The authorization covers the whole chain
+        unlock(authenticator);
+        send_chained(authenticator, 0x01, &data, 8).unwrap();
+
+        assert_eq!(calculate_code(authenticator, b"chained", 1), 94287082);
+    });
+}
+
+#[test]
+fn interrupted_chain_is_discarded() {
+    with_authenticator(|authenticator| {
+        let mut key = vec![0x21, 8];
+        key.extend_from_slice(b"12345678901234567890");
+        let mut data = tlv(0x71, b"chained");
+        data.extend(tlv(0x73, &key));
+
+        let mut first = apdu(0x01, 0x00, 0x00, &data[..10]);
+        first[0] |= 0x10;
+        send(authenticator, &first).unwrap();
+
+        // LIST interrupts the chain, so the remaining part alone is not a valid PUT
+        unlock(authenticator);
+        send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        unlock(authenticator);
+        assert_eq!(
+            send(authenticator, &apdu(0x01, 0x00, 0x00, &data[10..])),
+            Err(Status::IncorrectDataParameter)
+        );
+    });
+}
+
+#[test]
+fn chaining_buffer_overflow() {
+    with_authenticator(|authenticator| {
+        let part = [0x00; 255];
+        let mut command = apdu(0x01, 0x00, 0x00, &part);
+        command[0] |= 0x10;
+
+        // Four APDUs fit into the buffer of 1024 bytes, the fifth one does not
+        for _ in 0..4 {
+            send(authenticator, &command).unwrap();
+        }
+        assert_eq!(send(authenticator, &command), Err(Status::WrongLength));
+
+        // The buffer is cleared, and a new chain can be started
+        let mut key = vec![0x21, 8];
+        key.extend_from_slice(b"12345678901234567890");
+        let mut data = tlv(0x71, b"chained");
+        data.extend(tlv(0x73, &key));
+        unlock(authenticator);
+        send_chained(authenticator, 0x01, &data, 16).unwrap();
+        assert_eq!(calculate_code(authenticator, b"chained", 1), 94287082);
+    });
+}
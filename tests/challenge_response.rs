@@ -0,0 +1,92 @@
+mod common;
+
+use common::{apdu, register, send, tlv, unlock, with_authenticator};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// Key derived from the password by the client. Not checked by the device.
+const PASSWORD_KEY: [u8; 16] = [0x42; 16];
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the value of the first TLV with the given tag
+fn find_tag(data: &[u8], tag: u8) -> Option<&[u8]> {
+    let mut data = data;
+    while data.len() >= 2 {
+        let (t, len) = (data[0], data[1] as usize);
+        let value = &data[2..2 + len];
+        if t == tag {
+            return Some(value);
+        }
+        data = &data[2 + len..];
+    }
+    None
+}
+
+#[test]
+fn select_validate_calculate() {
+    with_authenticator(|authenticator| {
+        let answer_to_select = common::select(authenticator);
+        assert!(find_tag(&answer_to_select, 0x74).is_none());
+
+        // TOTP | SHA1, RFC 6238 test vector for T = 59
+        register(authenticator, b"totp", 0x21, 8, b"12345678901234567890");
+
+        // SET CODE
+        let host_challenge = [0x11; 8];
+        let mut key = vec![0x21];
+        key.extend_from_slice(&PASSWORD_KEY);
+        let mut data = tlv(0x73, &key);
+        data.extend(tlv(0x74, &host_challenge));
+        data.extend(tlv(0x75, &hmac_sha1(&PASSWORD_KEY, &host_challenge)));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x03, 0x00, 0x00, &data)).unwrap();
+
+        // SELECT has to announce the password now, with a fresh challenge, and a stable salt
+        let answer_to_select_with_password = common::select(authenticator);
+        assert_eq!(
+            find_tag(&answer_to_select, 0x71),
+            find_tag(&answer_to_select_with_password, 0x71)
+        );
+        let device_challenge = find_tag(&answer_to_select_with_password, 0x74)
+            .unwrap()
+            .to_vec();
+
+        // CALCULATE is not allowed before VALIDATE
+        let mut calculate = tlv(0x71, b"totp");
+        calculate.extend(tlv(0x74, &1u64.to_be_bytes()));
+        assert!(send(authenticator, &apdu(0xa2, 0x00, 0x01, &calculate)).is_err());
+
+        // VALIDATE with a wrong response is rejected
+        let mut data = tlv(0x75, &[0u8; 20]);
+        data.extend(tlv(0x74, &host_challenge));
+        assert!(send(authenticator, &apdu(0xa3, 0x00, 0x00, &data)).is_err());
+
+        // The challenge is rotated on each VALIDATE, so SELECT again
+        let answer_to_select_with_password = common::select(authenticator);
+        let device_challenge_rotated = find_tag(&answer_to_select_with_password, 0x74)
+            .unwrap()
+            .to_vec();
+        assert_ne!(device_challenge, device_challenge_rotated);
+
+        let mut data = tlv(0x75, &hmac_sha1(&PASSWORD_KEY, &device_challenge_rotated));
+        data.extend(tlv(0x74, &host_challenge));
+        let response = send(authenticator, &apdu(0xa3, 0x00, 0x00, &data)).unwrap();
+        assert_eq!(
+            find_tag(&response, 0x75).unwrap(),
+            hmac_sha1(&PASSWORD_KEY, &host_challenge).as_slice()
+        );
+
+        let response = send(authenticator, &apdu(0xa2, 0x00, 0x01, &calculate)).unwrap();
+        assert_eq!(&response[..3], &[0x76, 0x05, 0x08]);
+        let truncated = u32::from_be_bytes(response[3..7].try_into().unwrap());
+        assert_eq!((truncated & 0x7FFF_FFFF) % 10u32.pow(8), 94287082);
+
+        // The authorization is not kept for the following commands
+        assert!(send(authenticator, &apdu(0xa2, 0x00, 0x01, &calculate)).is_err());
+    });
+}
@@ -0,0 +1,265 @@
+//! Test harness running the application against the virtual Trussed platform,
+//! with the trussed-auth backend wired in the same way as in the usbip example.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+use trussed::virt::{self, Filesystem, Ram, StoreProvider};
+
+mod dispatch {
+    use trussed::{
+        api::{reply, request, Reply, Request},
+        backend::{Backend as _, BackendId},
+        error::Error,
+        platform::Platform,
+        serde_extensions::{ExtensionDispatch, ExtensionId, ExtensionImpl as _},
+        service::ServiceResources,
+        types::{Context, Location},
+    };
+    use trussed_auth::{AuthBackend, AuthContext, AuthExtension};
+
+    pub const BACKENDS: &[BackendId<Backend>] =
+        &[BackendId::Custom(Backend::Auth), BackendId::Core];
+
+    pub enum Backend {
+        Auth,
+    }
+
+    pub enum Extension {
+        Auth,
+    }
+
+    impl From<Extension> for u8 {
+        fn from(extension: Extension) -> Self {
+            match extension {
+                Extension::Auth => 0,
+            }
+        }
+    }
+
+    impl TryFrom<u8> for Extension {
+        type Error = Error;
+
+        fn try_from(id: u8) -> Result<Self, Self::Error> {
+            match id {
+                0 => Ok(Extension::Auth),
+                _ => Err(Error::InternalError),
+            }
+        }
+    }
+
+    pub struct Dispatch {
+        auth: AuthBackend,
+    }
+
+    #[derive(Default)]
+    pub struct DispatchContext {
+        auth: AuthContext,
+    }
+
+    impl Dispatch {
+        pub fn new() -> Self {
+            Self {
+                auth: AuthBackend::new(Location::Internal),
+            }
+        }
+    }
+
+    impl ExtensionDispatch for Dispatch {
+        type BackendId = Backend;
+        type Context = DispatchContext;
+        type ExtensionId = Extension;
+
+        fn core_request<P: Platform>(
+            &mut self,
+            backend: &Self::BackendId,
+            ctx: &mut Context<Self::Context>,
+            request: &Request,
+            resources: &mut ServiceResources<P>,
+        ) -> Result<Reply, Error> {
+            match backend {
+                Backend::Auth => {
+                    self.auth
+                        .request(&mut ctx.core, &mut ctx.backends.auth, request, resources)
+                }
+            }
+        }
+
+        fn extension_request<P: Platform>(
+            &mut self,
+            backend: &Self::BackendId,
+            extension: &Self::ExtensionId,
+            ctx: &mut Context<Self::Context>,
+            request: &request::SerdeExtension,
+            resources: &mut ServiceResources<P>,
+        ) -> Result<reply::SerdeExtension, Error> {
+            match backend {
+                Backend::Auth => match extension {
+                    Extension::Auth => self.auth.extension_request_serialized(
+                        &mut ctx.core,
+                        &mut ctx.backends.auth,
+                        request,
+                        resources,
+                    ),
+                },
+            }
+        }
+    }
+
+    impl ExtensionId<AuthExtension> for Dispatch {
+        type Id = Extension;
+
+        const ID: Self::Id = Self::Id::Auth;
+    }
+}
+
+pub type VirtClient<S = Ram> = virt::Client<S, dispatch::Dispatch>;
+
+pub const PIN: &[u8] = b"1234";
+
+/// Runs the closure with a freshly created application instance on a RAM filesystem.
+pub fn with_authenticator<R>(f: impl FnOnce(&mut Authenticator<VirtClient>) -> R) -> R {
+    virt::with_platform(Ram::default(), |platform| {
+        platform.run_client_with_backends(
+            "oath",
+            dispatch::Dispatch::new(),
+            dispatch::BACKENDS,
+            |client| {
+                let mut authenticator = Authenticator::new(client);
+                f(&mut authenticator)
+            },
+        )
+    })
+}
+
+/// Device with its filesystem kept in a temporary file, so it survives the power cycles.
+pub struct Device {
+    path: PathBuf,
+}
+
+impl Device {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "oath-authenticator-{}-{}.bin",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        Self { path }
+    }
+
+    /// Runs the closure with the Trussed client of the application, on the kept filesystem.
+    pub fn with_client<R>(&self, f: impl FnOnce(VirtClient<Filesystem>) -> R) -> R {
+        virt::with_platform(Filesystem::new(self.path.clone()), |platform| {
+            platform.run_client_with_backends(
+                "oath",
+                dispatch::Dispatch::new(),
+                dispatch::BACKENDS,
+                f,
+            )
+        })
+    }
+
+    /// Powers the device on, and runs the closure with a new application instance.
+    pub fn power_on<R>(
+        &self,
+        f: impl FnOnce(&mut Authenticator<VirtClient<Filesystem>>) -> R,
+    ) -> R {
+        self.with_client(|client| f(&mut Authenticator::new(client)))
+    }
+}
+
+impl Default for Device {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Sends a single raw APDU to the application and returns the response data.
+pub fn send<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    apdu: &[u8],
+) -> Result<heapless::Vec<u8, { 3 * 1024 }>, Status> {
+    let command = iso7816::Command::<{ 10 * 255 }>::try_from(apdu).expect("invalid APDU");
+    let mut response = heapless::Vec::new();
+    authenticator.respond(&command, &mut response)?;
+    Ok(response)
+}
+
+/// Sends a single raw APDU to the application, and returns the response data along with
+/// the status, as the data is kept when more of it is available.
+pub fn respond<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    apdu: &[u8],
+) -> (Vec<u8>, Result<(), Status>) {
+    let command = iso7816::Command::<{ 10 * 255 }>::try_from(apdu).expect("invalid APDU");
+    let mut response = heapless::Vec::<u8, { 3 * 1024 }>::new();
+    let status = authenticator.respond(&command, &mut response);
+    (response.to_vec(), status)
+}
+
+/// Builds a short APDU from its header and data field.
+pub fn apdu(ins: u8, p1: u8, p2: u8, data: &[u8]) -> Vec<u8> {
+    let mut apdu = vec![0x00, ins, p1, p2, data.len() as u8];
+    apdu.extend_from_slice(data);
+    apdu
+}
+
+pub fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut tlv = vec![tag, value.len() as u8];
+    tlv.extend_from_slice(value);
+    tlv
+}
+
+pub fn select<S: StoreProvider>(authenticator: &mut Authenticator<VirtClient<S>>) -> Vec<u8> {
+    let apdu = apdu(0xa4, 0x04, 0x00, oath_authenticator::YUBICO_OATH_AID);
+    send(authenticator, &apdu).unwrap().to_vec()
+}
+
+/// Sets the PIN on the first call, and verifies it, unlocking the credentials storage
+/// for the next command.
+pub fn unlock<S: StoreProvider>(authenticator: &mut Authenticator<VirtClient<S>>) {
+    send(authenticator, &apdu(0xb4, 0x00, 0x00, &tlv(0x80, PIN))).ok();
+    send(authenticator, &apdu(0xb2, 0x00, 0x00, &tlv(0x80, PIN))).unwrap();
+}
+
+/// Registers a credential with the given (kind | algorithm) byte, digits and secret.
+pub fn register<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    label: &[u8],
+    kind_algorithm: u8,
+    digits: u8,
+    secret: &[u8],
+) {
+    let mut key = vec![kind_algorithm, digits];
+    key.extend_from_slice(secret);
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x73, &key));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+/// Calculates the OTP code for the given credential, and decodes it to the final code.
+pub fn calculate_code<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    label: &[u8],
+    challenge: u64,
+) -> u32 {
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x74, &challenge.to_be_bytes()));
+    unlock(authenticator);
+    let response = send(authenticator, &apdu(0xa2, 0x00, 0x01, &data)).unwrap();
+    assert_eq!(&response[..2], &[0x76, 0x05]);
+    let digits = response[2] as u32;
+    let truncated = u32::from_be_bytes(response[3..7].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 10u32.pow(digits)
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::{apdu, register, select, send, tlv, unlock, with_authenticator};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// Seeds from RFC 6238, Appendix B, with the (kind | algorithm) byte and the HMAC output length
+const SEEDS: [(u8, &[u8], usize); 3] = [
+    (0x21, b"12345678901234567890", 20),
+    (0x22, b"12345678901234567890123456789012", 32),
+    (
+        0x23,
+        b"1234567890123456789012345678901234567890123456789012345678901234",
+        64,
+    ),
+];
+
+#[test]
+fn full_response_length_per_algorithm() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        for (kind_algorithm, seed, length) in SEEDS {
+            let label = [kind_algorithm];
+            register(authenticator, &label, kind_algorithm, 8, seed);
+
+            let mut data = tlv(0x71, &label);
+            data.extend(tlv(0x74, &1u64.to_be_bytes()));
+            unlock(authenticator);
+            let response = send(authenticator, &apdu(0xa2, 0x00, 0x00, &data)).unwrap();
+            assert_eq!(response[0], 0x75);
+            assert_eq!(response[1] as usize, length + 1);
+            assert_eq!(response[2], 8);
+            assert_eq!(response.len(), length + 3);
+        }
+    });
+}
+
+#[test]
+fn full_response_is_the_hmac() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let (kind_algorithm, seed, _) = SEEDS[0];
+        register(authenticator, b"sha1", kind_algorithm, 8, seed);
+
+        let mut data = tlv(0x71, b"sha1");
+        data.extend(tlv(0x74, &1u64.to_be_bytes()));
+        unlock(authenticator);
+        let response = send(authenticator, &apdu(0xa2, 0x00, 0x00, &data)).unwrap();
+        let mut mac = Hmac::<Sha1>::new_from_slice(seed).unwrap();
+        mac.update(&1u64.to_be_bytes());
+        assert_eq!(&response[3..], &mac.finalize().into_bytes()[..]);
+    });
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{apdu, select, send, tlv, unlock, with_authenticator};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+fn base32_decode(data: &str) -> Vec<u8> {
+    const ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let (mut buffer, mut bits, mut decoded) = (0u32, 0, Vec::new());
+    for c in data.chars() {
+        buffer = (buffer << 5) | ALPHABET.find(c).unwrap() as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    decoded
+}
+
+#[test]
+fn generated_secret_is_returned_once() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // HOTP | SHA1, 6 digits, no secret
+        let mut data = tlv(0x71, b"issuer:gen");
+        data.extend(tlv(0x73, &[0x11, 6]));
+        unlock(authenticator);
+        let response = send(authenticator, &apdu(0x01, 0x01, 0x00, &data)).unwrap();
+
+        assert_eq!(&response[..2], &[0x73, 20]);
+        let secret = response[2..22].to_vec();
+        assert_eq!(response[22], 0x99);
+        let uri = core::str::from_utf8(&response[24..24 + response[23] as usize]).unwrap();
+        let (prefix, rest) = uri.split_once("?secret=").unwrap();
+        assert_eq!(prefix, "otpauth://hotp/issuer:gen");
+        let (encoded, parameters) = rest.split_once('&').unwrap();
+        assert_eq!(base32_decode(encoded), secret);
+        assert_eq!(parameters, "algorithm=SHA1&digits=6&counter=0");
+
+        // The device calculates the codes with the returned secret
+        let mut data = tlv(0x71, b"issuer:gen");
+        data.extend(tlv(0x74, &[0; 8]));
+        unlock(authenticator);
+        let response = send(authenticator, &apdu(0xa2, 0x00, 0x01, &data)).unwrap();
+        let mut mac = Hmac::<Sha1>::new_from_slice(&secret).unwrap();
+        mac.update(&0u64.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        let offset = (digest[19] & 0xf) as usize;
+        let truncated = u32::from_be_bytes(digest[offset..][..4].try_into().unwrap()) & 0x7fff_ffff;
+        assert_eq!(
+            &response[..],
+            &[&[0x76, 5, 6][..], &truncated.to_be_bytes()].concat()
+        );
+
+        // A secret sent by the host is refused
+        let mut data = tlv(0x71, b"issuer:gen");
+        data.extend(tlv(0x73, &[0x11, 6, 0x42, 0x42]));
+        unlock(authenticator);
+        assert!(send(authenticator, &apdu(0x01, 0x01, 0x00, &data)).is_err());
+    });
+}
+
+#[test]
+fn uri_fits_percent_encoded_label_and_issuer() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // Each byte of the label and issuer is percent-encoded to three characters
+        let label = [b'/'; 120];
+        let mut data = tlv(0x71, &label);
+        data.extend(tlv(0x73, &[0x11, 6]));
+        data.extend(tlv(0x9a, &label));
+        unlock(authenticator);
+        let response = send(authenticator, &apdu(0x01, 0x01, 0x00, &data)).unwrap();
+
+        assert_eq!(&response[..2], &[0x73, 20]);
+        assert_eq!(&response[22..24], &[0x99, 0x82]);
+        let length = u16::from_be_bytes(response[24..26].try_into().unwrap()) as usize;
+        let uri = core::str::from_utf8(&response[26..]).unwrap();
+        assert_eq!(uri.len(), length);
+        let encoded = "%2F".repeat(120);
+        assert!(uri.starts_with(&format!("otpauth://hotp/{}?secret=", encoded)));
+        assert!(uri.ends_with(&format!("&issuer={}", encoded)));
+
+        // The credential is stored
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert_eq!(&list[..3], &[0x72, 121, 0x11]);
+    });
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::{apdu, calculate_code, select, send, tlv, unlock, with_authenticator};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// RFC 4226 test secret
+const SECRET: &[u8] = b"12345678901234567890";
+
+/// HOTP code as in RFC 4226, section 5.3
+fn hotp(counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(SECRET).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0xf) as usize;
+    let truncated = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 1_000_000
+}
+
+/// Registers the HOTP credential with the initial counter sent under the given tag
+fn register_hotp(
+    authenticator: &mut oath_authenticator::Authenticator<common::VirtClient>,
+    tag: u8,
+    counter: &[u8],
+) {
+    // HOTP | SHA1, 6 digits
+    let mut key = vec![0x11, 6];
+    key.extend_from_slice(SECRET);
+    let mut data = tlv(0x71, b"hotp");
+    data.extend(tlv(0x73, &key));
+    data.extend(tlv(tag, counter));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+#[test]
+fn counter_above_u32_max() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let counter = u64::from(u32::MAX) + 5;
+        register_hotp(authenticator, 0x83, &counter.to_be_bytes());
+
+        // The counter is used in full, and incremented past the 32-bit range
+        for counter in counter..counter + 3 {
+            assert_eq!(calculate_code(authenticator, b"hotp", 0), hotp(counter));
+        }
+    });
+}
+
+#[test]
+fn counter_crosses_u32_max() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_hotp(authenticator, 0x83, &u64::from(u32::MAX).to_be_bytes());
+
+        assert_eq!(
+            calculate_code(authenticator, b"hotp", 0),
+            hotp(u32::MAX.into())
+        );
+        assert_eq!(
+            calculate_code(authenticator, b"hotp", 0),
+            hotp(u64::from(u32::MAX) + 1)
+        );
+    });
+}
+
+#[test]
+fn counter_32_bit_tag() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // ykman sends the initial moving factor as a 32-bit value
+        register_hotp(authenticator, 0x7a, &5u32.to_be_bytes());
+
+        assert_eq!(calculate_code(authenticator, b"hotp", 0), hotp(5));
+        assert_eq!(calculate_code(authenticator, b"hotp", 0), hotp(6));
+    });
+}
+
+/// The counter field of the stored credential, before the 64-bit counter support
+#[derive(serde::Serialize)]
+struct LegacyCredential {
+    #[serde(rename = "T")]
+    touch_required: bool,
+    #[serde(rename = "C")]
+    counter: Option<u32>,
+}
+
+/// The counter field of the stored credential, as read now
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+struct Credential {
+    #[serde(rename = "T")]
+    touch_required: bool,
+    #[serde(rename = "C")]
+    counter: Option<u64>,
+}
+
+#[test]
+fn legacy_counter_loads() {
+    let legacy = LegacyCredential {
+        touch_required: false,
+        counter: Some(u32::MAX),
+    };
+    let mut buffer = [0; 64];
+    let serialized = trussed::cbor_serialize(&legacy, &mut buffer)
+        .unwrap()
+        .to_vec();
+
+    let credential: Credential = trussed::cbor_deserialize(&serialized).unwrap();
+    assert_eq!(
+        credential,
+        Credential {
+            touch_required: false,
+            counter: Some(u32::MAX.into()),
+        }
+    );
+
+    // CBOR encodes the integers in their shortest form, so the record is the same
+    // until the counter grows past the 32-bit range
+    let mut buffer = [0; 64];
+    assert_eq!(
+        trussed::cbor_serialize(&credential, &mut buffer).unwrap(),
+        &serialized[..]
+    );
+}
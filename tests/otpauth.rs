@@ -0,0 +1,138 @@
+mod common;
+
+use common::{apdu, calculate_code, select, send, tlv, unlock, with_authenticator, VirtClient};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+
+fn register_uri(authenticator: &mut Authenticator<VirtClient>, uri: &str) -> Result<(), Status> {
+    unlock(authenticator);
+    send(
+        authenticator,
+        &apdu(0x01, 0x00, 0x00, &tlv(0x99, uri.as_bytes())),
+    )
+    .map(drop)
+}
+
+#[test]
+fn totp_from_uri() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // RFC 6238 SHA1 secret "12345678901234567890", in lower case and with the padding
+        register_uri(
+            authenticator,
+            "otpauth://totp/ACME%20Co:john%40example.com?secret=gezdgnbvgy3tqojqgezdgnbvgy3tqojq====\
+             &issuer=ACME%20Co&digits=8&period=30&algorithm=SHA1",
+        )
+        .unwrap();
+
+        // The label is percent-decoded, and the issuer and account are kept apart
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x01, 0x00, &[])).unwrap();
+        assert_eq!(
+            &list[..],
+            b"\x72\x19\x21ACME Co:john@example.com\x9a\x07ACME Co\x9b\x10john@example.com"
+        );
+
+        assert_eq!(
+            calculate_code(authenticator, b"ACME Co:john@example.com", 1),
+            94287082
+        );
+    });
+}
+
+#[test]
+fn hotp_from_uri() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // RFC 4226 secret, starting from the counter 5
+        register_uri(
+            authenticator,
+            "otpauth://hotp/hotp?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=5",
+        )
+        .unwrap();
+        assert_eq!(calculate_code(authenticator, b"hotp", 0), 254676);
+        assert_eq!(calculate_code(authenticator, b"hotp", 0), 287922);
+    });
+}
+
+#[test]
+fn issuer_from_label() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_uri(
+            authenticator,
+            "otpauth://totp/Example:%20alice?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ",
+        )
+        .unwrap();
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x01, 0x00, &[])).unwrap();
+        assert_eq!(
+            &list[..],
+            b"\x72\x0f\x21Example: alice\x9a\x07Example\x9b\x05alice"
+        );
+    });
+}
+
+#[test]
+fn issuer_account_and_display_name_in_put() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut data = tlv(0x71, b"label");
+        data.extend(tlv(0x73, &[0x21, 6, 0x42, 0x42]));
+        data.extend(tlv(0x9a, b"Example"));
+        data.extend(tlv(0x9b, b"alice"));
+        data.extend(tlv(0x9c, b"Alice at Example"));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x01, 0x00, &[])).unwrap();
+        assert_eq!(
+            &list[..],
+            b"\x72\x06\x21label\x9a\x07Example\x9b\x05alice\x9c\x10Alice at Example"
+        );
+
+        // The YKOATH clients get the name list entries alone
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert_eq!(&list[..], b"\x72\x06\x21label");
+    });
+}
+
+#[test]
+fn invalid_uris_are_refused() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        for uri in [
+            "https://totp/label?secret=GEZDGNBV",
+            "otpauth://totp/label",
+            "otpauth://totp/?secret=GEZDGNBV",
+            "otpauth://totp/label?secret=GEZDGNB1",
+            "otpauth://totp/label?secret=",
+            "otpauth://totp/label?secret=GEZDGNBV&digits=six",
+            "otpauth://totp/label%2?secret=GEZDGNBV",
+        ] {
+            assert_eq!(
+                register_uri(authenticator, uri),
+                Err(Status::IncorrectDataParameter),
+                "{}",
+                uri
+            );
+        }
+        for uri in [
+            "otpauth://steam/label?secret=GEZDGNBV",
+            "otpauth://totp/label?secret=GEZDGNBV&algorithm=MD5",
+            "otpauth://totp/label?secret=GEZDGNBV&digits=10",
+            "otpauth://totp/label?secret=GEZDGNBV&counter=1",
+            "otpauth://hotp/label?secret=GEZDGNBV&period=60",
+            "otpauth://totp/label?secret=GEZDGNBV&period=0",
+        ] {
+            assert_eq!(
+                register_uri(authenticator, uri),
+                Err(Status::FunctionNotSupported),
+                "{}",
+                uri
+            );
+        }
+    });
+}
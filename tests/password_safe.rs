@@ -0,0 +1,56 @@
+mod common;
+
+use common::{apdu, select, send, tlv, unlock, with_authenticator};
+use iso7816::Status;
+
+#[test]
+fn malformed_optional_fields_are_refused() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut data = tlv(0x71, b"label");
+        data.extend(tlv(0x73, &[0x21, 6, 0x42, 0x42]));
+        data.extend(tlv(0x84, b"login"));
+        // the length runs past the end of the data
+        data.extend([0x85, 0x10, b'a']);
+        unlock(authenticator);
+        assert_eq!(
+            send(authenticator, &apdu(0x01, 0x00, 0x00, &data)),
+            Err(Status::IncorrectDataParameter)
+        );
+
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert!(list.is_empty());
+    });
+}
+
+#[test]
+fn fields_are_returned_after_pin_verification() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut data = tlv(0x71, b"label");
+        data.extend(tlv(0x73, &[0x21, 6, 0x42, 0x42]));
+        data.extend(tlv(0x84, b"login"));
+        data.extend(tlv(0x85, b"password"));
+        data.extend(tlv(0x86, b"metadata"));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        let get_credential = apdu(0xb5, 0x00, 0x00, &tlv(0x71, b"label"));
+        select(authenticator);
+        assert_eq!(
+            send(authenticator, &get_credential),
+            Err(Status::ConditionsOfUseNotSatisfied)
+        );
+
+        unlock(authenticator);
+        let mut expected = tlv(0x71, b"label");
+        expected.extend(tlv(0x84, b"login"));
+        expected.extend(tlv(0x85, b"password"));
+        expected.extend(tlv(0x86, b"metadata"));
+        assert_eq!(
+            send(authenticator, &get_credential).unwrap().as_slice(),
+            expected.as_slice()
+        );
+    });
+}
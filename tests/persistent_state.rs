@@ -0,0 +1,91 @@
+mod common;
+
+use common::{apdu, select, send, Device};
+use iso7816::Status;
+use trussed::{
+    client::FilesystemClient,
+    syscall,
+    types::{Location, Message, PathBuf},
+};
+
+/// Returns the value of the first TLV with the given tag
+fn find_tag(data: &[u8], tag: u8) -> Option<&[u8]> {
+    let mut data = data;
+    while data.len() >= 2 {
+        let (t, len) = (data[0], data[1] as usize);
+        let value = &data[2..2 + len];
+        if t == tag {
+            return Some(value);
+        }
+        data = &data[2 + len..];
+    }
+    None
+}
+
+#[test]
+fn state_is_created_on_first_use() {
+    let device = Device::new();
+    let salt = device.power_on(|authenticator| {
+        let answer_to_select = select(authenticator);
+        assert!(find_tag(&answer_to_select, 0x9d).is_none());
+        let salt = find_tag(&answer_to_select, 0x71).unwrap().to_vec();
+        assert_eq!(
+            find_tag(&select(authenticator), 0x71),
+            Some(salt.as_slice())
+        );
+        salt
+    });
+
+    // The salt, used as the device ID, is kept over the power cycles
+    device.power_on(|authenticator| {
+        assert_eq!(
+            find_tag(&select(authenticator), 0x71),
+            Some(salt.as_slice())
+        );
+    });
+}
+
+#[test]
+fn corrupted_state_is_kept_until_reset() {
+    let device = Device::new();
+    device.with_client(|mut client| {
+        syscall!(client.write_file(
+            Location::Internal,
+            PathBuf::from("state.bin"),
+            Message::from_slice(b"corrupted").unwrap(),
+            None
+        ));
+    });
+
+    for _ in 0..2 {
+        device.power_on(|authenticator| {
+            // SELECT reports the state, so the client could offer the reset
+            let answer_to_select = select(authenticator);
+            assert!(find_tag(&answer_to_select, 0x79).is_some());
+            assert_eq!(find_tag(&answer_to_select, 0x9d), Some(&[0x01][..]));
+            assert!(find_tag(&answer_to_select, 0x71).is_none());
+
+            // Everything else is refused, and the state is not overwritten
+            assert_eq!(
+                send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])),
+                Err(Status::UnspecifiedPersistentExecutionError)
+            );
+            assert_eq!(
+                send(
+                    authenticator,
+                    &apdu(0xb4, 0x00, 0x00, &[0x80, 0x04, b'1', b'2', b'3', b'4'])
+                ),
+                Err(Status::UnspecifiedPersistentExecutionError)
+            );
+        });
+    }
+
+    device.power_on(|authenticator| {
+        send(authenticator, &apdu(0x04, 0xde, 0xad, &[])).unwrap();
+
+        let answer_to_select = select(authenticator);
+        assert!(find_tag(&answer_to_select, 0x9d).is_none());
+        assert!(find_tag(&answer_to_select, 0x71).is_some());
+        send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+    });
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{apdu, select, send, tlv, unlock, with_authenticator, VirtClient};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+
+fn use_recovery_code(
+    authenticator: &mut Authenticator<VirtClient>,
+    code: Option<&[u8]>,
+) -> Result<Vec<u8>, iso7816::Status> {
+    let mut data = tlv(0x71, b"recovery");
+    if let Some(code) = code {
+        data.extend(tlv(0x97, code));
+    }
+    unlock(authenticator);
+    send(authenticator, &apdu(0xba, 0x00, 0x00, &data)).map(|response| response.to_vec())
+}
+
+fn register_recovery_codes(authenticator: &mut Authenticator<VirtClient>) {
+    let mut codes = Vec::new();
+    for code in [&b"code-one"[..], b"code-two", b"code-three"] {
+        codes.push(code.len() as u8);
+        codes.extend_from_slice(code);
+    }
+    let mut data = tlv(0x71, b"recovery");
+    data.extend(tlv(0x73, &[0x71, 0]));
+    data.extend(tlv(0x96, &codes));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+#[test]
+fn recovery_codes_are_used_once() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_recovery_codes(authenticator);
+
+        // The List output is kept as in YKOATH, while its details mode and GetCredential report
+        // the remaining count
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap();
+        assert_eq!(&list[..], b"\x72\x09\x71recovery");
+        unlock(authenticator);
+        let list = send(authenticator, &apdu(0xa1, 0x01, 0x00, &[])).unwrap();
+        assert_eq!(&list[..], b"\x72\x09\x71recovery\x98\x01\x03");
+        unlock(authenticator);
+        let response = send(
+            authenticator,
+            &apdu(0xb5, 0x00, 0x00, &tlv(0x71, b"recovery")),
+        )
+        .unwrap();
+        assert_eq!(&response[..], b"\x71\x08recovery\x98\x01\x03");
+
+        // The next unused code is returned, and burned
+        let response = use_recovery_code(authenticator, None).unwrap();
+        assert_eq!(response, b"\x97\x08code-one\x98\x01\x02");
+
+        // The presented code is verified, and burned as well
+        let response = use_recovery_code(authenticator, Some(b"code-three")).unwrap();
+        assert_eq!(response, b"\x98\x01\x01");
+        assert!(use_recovery_code(authenticator, Some(b"code-three")).is_err());
+        assert!(use_recovery_code(authenticator, Some(b"code-one")).is_err());
+
+        let response = use_recovery_code(authenticator, None).unwrap();
+        assert_eq!(response, b"\x97\x08code-two\x98\x01\x00");
+        assert!(use_recovery_code(authenticator, None).is_err());
+    });
+}
+
+#[test]
+fn failed_verifications_are_limited_per_power_cycle() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_recovery_codes(authenticator);
+
+        for _ in 0..3 {
+            assert_eq!(
+                use_recovery_code(authenticator, Some(b"wrong-code")),
+                Err(Status::VerificationFailed)
+            );
+        }
+        // Even the valid code is refused now, and it is not burned
+        assert_eq!(
+            use_recovery_code(authenticator, Some(b"code-two")),
+            Err(Status::OperationBlocked)
+        );
+
+        // Revealing the code after the touch confirmation is still possible
+        let response = use_recovery_code(authenticator, None).unwrap();
+        assert_eq!(&response[..10], b"\x97\x08code-one");
+    });
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use common::{
+    apdu, calculate_code, register, select, send, tlv, unlock, with_authenticator, VirtClient,
+};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+
+/// RFC 6238 test secret for HMAC-SHA1
+const SECRET: &[u8] = b"12345678901234567890";
+
+fn rename(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    new_label: &[u8],
+) -> Result<(), Status> {
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x71, new_label));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x05, 0x00, 0x00, &data)).map(drop)
+}
+
+/// Returns the labels of the listed credentials
+fn list(authenticator: &mut Authenticator<VirtClient>) -> Vec<Vec<u8>> {
+    unlock(authenticator);
+    let mut data = &send(authenticator, &apdu(0xa1, 0x00, 0x00, &[])).unwrap()[..];
+    let mut labels = Vec::new();
+    while !data.is_empty() {
+        let (entry, rest) = data[2..].split_at(data[1] as usize);
+        labels.push(entry[1..].to_vec());
+        data = rest;
+    }
+    labels.sort();
+    labels
+}
+
+#[test]
+fn rename_keeps_the_secret() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // TOTP | SHA1, RFC 6238 test vector for T = 59
+        register(authenticator, b"old", 0x21, 8, SECRET);
+
+        rename(authenticator, b"old", b"new").unwrap();
+        assert_eq!(list(authenticator), [b"new".to_vec()]);
+        assert_eq!(calculate_code(authenticator, b"new", 1), 94287082);
+    });
+}
+
+#[test]
+fn rename_to_existing_label_is_refused() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"first", 0x21, 8, SECRET);
+        register(authenticator, b"second", 0x21, 8, SECRET);
+
+        assert_eq!(
+            rename(authenticator, b"first", b"second"),
+            Err(Status::IncorrectDataParameter)
+        );
+        assert_eq!(
+            rename(authenticator, b"first", b""),
+            Err(Status::IncorrectDataParameter)
+        );
+        assert_eq!(list(authenticator), [b"first".to_vec(), b"second".to_vec()]);
+    });
+}
+
+#[test]
+fn rename_of_missing_credential_is_refused() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"first", 0x21, 8, SECRET);
+
+        assert_eq!(
+            rename(authenticator, b"missing", b"new"),
+            Err(Status::NotFound)
+        );
+        assert_eq!(list(authenticator), [b"first".to_vec()]);
+    });
+}
@@ -0,0 +1,313 @@
+mod common;
+
+use common::{apdu, register, select, send, tlv, unlock, with_authenticator, Device, VirtClient};
+use hmac::{Hmac, Mac};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+use sha1::Sha1;
+use trussed::virt::StoreProvider;
+
+/// RFC 4226 test secret
+const SECRET: &[u8] = b"12345678901234567890";
+
+/// HOTP code as in RFC 4226, section 5.3
+fn hotp(counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(SECRET).unwrap();
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0xf) as usize;
+    let truncated = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 1_000_000
+}
+
+fn verify_code<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    code: u32,
+) -> Result<(), Status> {
+    let mut data = tlv(0x71, b"reverse");
+    data.extend(tlv(0x75, &code.to_be_bytes()));
+    send(authenticator, &apdu(0xb1, 0x00, 0x00, &data)).map(drop)
+}
+
+fn resync_code<S: StoreProvider>(
+    authenticator: &mut Authenticator<VirtClient<S>>,
+    code: u32,
+    next_code: u32,
+) -> Result<(), Status> {
+    let mut data = tlv(0x71, b"reverse");
+    data.extend(tlv(0x75, &code.to_be_bytes()));
+    data.extend(tlv(0x75, &next_code.to_be_bytes()));
+    unlock(authenticator);
+    send(authenticator, &apdu(0xb6, 0x00, 0x00, &data)).map(drop)
+}
+
+/// Returns the value of the first TLV with the given tag
+fn find_tag(data: &[u8], tag: u8) -> Option<&[u8]> {
+    let mut data = data;
+    while data.len() >= 2 {
+        let (t, len) = (data[0], data[1] as usize);
+        let value = &data[2..2 + len];
+        if t == tag {
+            return Some(value);
+        }
+        data = &data[2 + len..];
+    }
+    None
+}
+
+/// Runs GetCounter, which needs no PIN either
+fn get_counter<S: StoreProvider>(authenticator: &mut Authenticator<VirtClient<S>>) -> Vec<u8> {
+    send(
+        authenticator,
+        &apdu(0xb7, 0x00, 0x00, &tlv(0x71, b"reverse")),
+    )
+    .unwrap()
+    .to_vec()
+}
+
+fn counter<S: StoreProvider>(authenticator: &mut Authenticator<VirtClient<S>>) -> u64 {
+    let response = get_counter(authenticator);
+    u64::from_be_bytes(find_tag(&response, 0x83).unwrap().try_into().unwrap())
+}
+
+/// Registers the HOTP reverse | SHA1 credential with the given look-ahead window
+fn register_with_window(
+    authenticator: &mut Authenticator<VirtClient>,
+    window: u8,
+) -> Result<(), Status> {
+    let mut key = vec![0x31, 6];
+    key.extend_from_slice(SECRET);
+    let mut data = tlv(0x71, b"reverse");
+    data.extend(tlv(0x73, &key));
+    data.extend(tlv(0x87, &[window]));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).map(drop)
+}
+
+#[test]
+fn rfc4226_test_vectors() {
+    assert_eq!(
+        (0..10).map(hotp).collect::<Vec<_>>(),
+        [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489]
+    );
+}
+
+#[test]
+fn verification_works_without_pin_after_power_cycle() {
+    let device = Device::new();
+    device.power_on(|authenticator| {
+        select(authenticator);
+        // HOTP reverse | SHA1, registered without properties once the PIN is set
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+    });
+
+    // The PIN is not verified after the power cycle
+    device.power_on(|authenticator| {
+        select(authenticator);
+        assert_eq!(counter(authenticator), 0);
+        let mut data = tlv(0x71, b"reverse");
+        data.extend(tlv(0x75, &hotp(0).to_be_bytes()));
+        let response = send(authenticator, &apdu(0xb1, 0x00, 0x00, &data)).unwrap();
+        assert_eq!(&response[..], &[0x77, 0x00]);
+        assert_eq!(counter(authenticator), 1);
+    });
+}
+
+#[test]
+fn get_counter_reports_the_credential() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // HOTP reverse | SHA256, 8 digits, starting at the counter 5
+        let mut key = vec![0x32, 8];
+        key.extend_from_slice(SECRET);
+        let mut data = tlv(0x71, b"reverse");
+        data.extend(tlv(0x73, &key));
+        data.extend(tlv(0x7a, &5u32.to_be_bytes()));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        let response = get_counter(authenticator);
+        assert_eq!(find_tag(&response, 0x71), Some(&b"reverse"[..]));
+        assert_eq!(find_tag(&response, 0x7b), Some(&[0x32][..]));
+        assert_eq!(find_tag(&response, 0x89), Some(&[8][..]));
+        assert_eq!(find_tag(&response, 0x83), Some(&5u64.to_be_bytes()[..]));
+
+        // The other kinds have no counter to report, even with the PIN verified
+        register(authenticator, b"hotp", 0x11, 6, SECRET);
+        register(authenticator, b"totp", 0x21, 6, SECRET);
+        for label in [&b"hotp"[..], b"totp"] {
+            unlock(authenticator);
+            assert_eq!(
+                send(authenticator, &apdu(0xb7, 0x00, 0x00, &tlv(0x71, label))),
+                Err(Status::ConditionsOfUseNotSatisfied)
+            );
+        }
+        assert_eq!(
+            send(
+                authenticator,
+                &apdu(0xb7, 0x00, 0x00, &tlv(0x71, b"missing"))
+            ),
+            Err(Status::NotFound)
+        );
+    });
+}
+
+#[test]
+fn default_window() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+
+        // REVERSE_HOTP_WINDOW_DEFAULT is 9
+        assert_eq!(find_tag(&get_counter(authenticator), 0x87), Some(&[9][..]));
+        assert_eq!(
+            verify_code(authenticator, hotp(10)),
+            Err(Status::RemainingRetries(7))
+        );
+        verify_code(authenticator, hotp(9)).unwrap();
+        assert_eq!(counter(authenticator), 10);
+    });
+}
+
+#[test]
+fn window_up_to_its_max() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // REVERSE_HOTP_WINDOW_MAX is 64
+        assert_eq!(
+            register_with_window(authenticator, 65),
+            Err(Status::IncorrectDataParameter)
+        );
+        register_with_window(authenticator, 64).unwrap();
+
+        // The effective window is reported by both GetCounter and GetCredential
+        assert_eq!(find_tag(&get_counter(authenticator), 0x87), Some(&[64][..]));
+        unlock(authenticator);
+        let credential = send(
+            authenticator,
+            &apdu(0xb5, 0x00, 0x00, &tlv(0x71, b"reverse")),
+        )
+        .unwrap();
+        assert_eq!(find_tag(&credential, 0x87), Some(&[64][..]));
+
+        assert_eq!(
+            verify_code(authenticator, hotp(65)),
+            Err(Status::RemainingRetries(7))
+        );
+        verify_code(authenticator, hotp(64)).unwrap();
+        assert_eq!(counter(authenticator), 65);
+    });
+}
+
+#[test]
+fn resync_with_two_consecutive_codes() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // HOTP reverse | SHA1
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+
+        // The counter drifted past the default look-ahead window of 9
+        assert_eq!(
+            verify_code(authenticator, hotp(100)),
+            Err(Status::RemainingRetries(7))
+        );
+
+        // The codes have to be consecutive
+        assert_eq!(
+            resync_code(authenticator, hotp(100), hotp(102)),
+            Err(Status::VerificationFailed)
+        );
+        resync_code(authenticator, hotp(100), hotp(101)).unwrap();
+
+        // The counter is set after the second code, which can't be used again
+        assert_eq!(
+            verify_code(authenticator, hotp(101)),
+            Err(Status::RemainingRetries(7))
+        );
+        verify_code(authenticator, hotp(102)).unwrap();
+    });
+}
+
+#[test]
+fn resync_is_limited_to_its_window() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+
+        // REVERSE_HOTP_RESYNC_WINDOW is 500
+        resync_code(authenticator, hotp(499), hotp(500)).unwrap();
+        assert_eq!(
+            resync_code(authenticator, hotp(1001), hotp(1002)),
+            Err(Status::VerificationFailed)
+        );
+        verify_code(authenticator, hotp(501)).unwrap();
+    });
+}
+
+#[test]
+fn failures_are_limited_per_power_cycle() {
+    let device = Device::new();
+    device.power_on(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+
+        // FAILURES_PER_POWER_CYCLE_MAX is 3
+        for retries in [7, 6, 5] {
+            assert_eq!(
+                verify_code(authenticator, hotp(100)),
+                Err(Status::RemainingRetries(retries))
+            );
+        }
+        // The valid code is refused as well, and the persistent counter is left intact
+        assert_eq!(
+            verify_code(authenticator, hotp(0)),
+            Err(Status::OperationBlocked)
+        );
+    });
+
+    device.power_on(|authenticator| {
+        verify_code(authenticator, hotp(0)).unwrap();
+        // A successful verification restores all the attempts
+        assert_eq!(
+            verify_code(authenticator, hotp(100)),
+            Err(Status::RemainingRetries(7))
+        );
+    });
+}
+
+#[test]
+fn verification_is_locked_after_failures() {
+    let device = Device::new();
+    device.power_on(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"reverse", 0x31, 6, SECRET);
+    });
+
+    // VERIFY_CODE_DEFAULT_RETRIES is 8, used up over a few power cycles
+    for retries in [&[7, 6, 5][..], &[4, 3, 2], &[1, 0]] {
+        device.power_on(|authenticator| {
+            for &retries in retries {
+                assert_eq!(
+                    verify_code(authenticator, hotp(100)),
+                    Err(Status::RemainingRetries(retries))
+                );
+            }
+        });
+    }
+
+    device.power_on(|authenticator| {
+        // The valid code is refused after the power cycle as well
+        assert_eq!(
+            verify_code(authenticator, hotp(0)),
+            Err(Status::OperationBlocked)
+        );
+
+        // The lock is kept until the counter is resynchronized
+        resync_code(authenticator, hotp(0), hotp(1)).unwrap();
+        verify_code(authenticator, hotp(2)).unwrap();
+        assert_eq!(
+            verify_code(authenticator, hotp(100)),
+            Err(Status::RemainingRetries(7))
+        );
+    });
+}
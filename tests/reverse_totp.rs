@@ -0,0 +1,131 @@
+mod common;
+
+use common::{apdu, register, select, send, tlv, unlock, with_authenticator, VirtClient};
+use hmac::{Hmac, Mac};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+use sha1::Sha1;
+
+/// RFC 6238 test secret for HMAC-SHA1
+const SECRET: &[u8] = b"12345678901234567890";
+
+/// TOTP code for the given time step, as in RFC 6238
+fn totp(time_step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(SECRET).unwrap();
+    mac.update(&time_step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0xf) as usize;
+    let truncated = u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 1_000_000
+}
+
+fn verify_code(
+    authenticator: &mut Authenticator<VirtClient>,
+    code: u32,
+    time_step: u64,
+) -> Result<(), Status> {
+    let mut data = tlv(0x71, b"reverse");
+    data.extend(tlv(0x75, &code.to_be_bytes()));
+    data.extend(tlv(0x74, &time_step.to_be_bytes()));
+    send(authenticator, &apdu(0xb1, 0x00, 0x00, &data)).map(drop)
+}
+
+/// The lowest time step accepted in the next verification, as reported by GetCounter
+fn next_time_step(authenticator: &mut Authenticator<VirtClient>) -> u64 {
+    let response = send(
+        authenticator,
+        &apdu(0xb7, 0x00, 0x00, &tlv(0x71, b"reverse")),
+    )
+    .unwrap();
+    let mut data = &response[..];
+    while data[0] != 0x83 {
+        data = &data[2 + data[1] as usize..];
+    }
+    u64::from_be_bytes(data[2..10].try_into().unwrap())
+}
+
+#[test]
+fn codes_are_accepted_within_the_time_window() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // TOTP reverse | SHA1
+        register(authenticator, b"reverse", 0x41, 6, SECRET);
+
+        // REVERSE_TOTP_WINDOW is 1 time step before and after the one sent
+        assert_eq!(
+            verify_code(authenticator, totp(98), 100),
+            Err(Status::RemainingRetries(7))
+        );
+        assert_eq!(
+            verify_code(authenticator, totp(102), 100),
+            Err(Status::RemainingRetries(6))
+        );
+        verify_code(authenticator, totp(99), 100).unwrap();
+        assert_eq!(next_time_step(authenticator), 100);
+
+        verify_code(authenticator, totp(201), 200).unwrap();
+        assert_eq!(next_time_step(authenticator), 202);
+
+        // The time step is required
+        let mut data = tlv(0x71, b"reverse");
+        data.extend(tlv(0x75, &totp(202).to_be_bytes()));
+        assert_eq!(
+            send(authenticator, &apdu(0xb1, 0x00, 0x00, &data)),
+            Err(Status::IncorrectDataParameter)
+        );
+    });
+}
+
+#[test]
+fn replayed_time_steps_are_rejected() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"reverse", 0x41, 6, SECRET);
+
+        verify_code(authenticator, totp(100), 100).unwrap();
+        // The same code, sent again within its time window
+        assert_eq!(
+            verify_code(authenticator, totp(100), 100),
+            Err(Status::RemainingRetries(7))
+        );
+        assert_eq!(
+            verify_code(authenticator, totp(100), 101),
+            Err(Status::RemainingRetries(6))
+        );
+        // The host clock going back is not accepted either
+        assert_eq!(
+            verify_code(authenticator, totp(50), 50),
+            Err(Status::VerificationFailed)
+        );
+        verify_code(authenticator, totp(101), 101).unwrap();
+        assert_eq!(next_time_step(authenticator), 102);
+    });
+}
+
+#[test]
+fn period_and_t0_are_left_to_the_host() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // The time step is calculated by the host, so these can't be set on the device
+        for extension in [
+            tlv(0x8a, &60u32.to_be_bytes()),
+            tlv(0x8b, &1000u64.to_be_bytes()),
+        ] {
+            let mut key = vec![0x41, 6];
+            key.extend_from_slice(SECRET);
+            let mut data = tlv(0x71, b"reverse");
+            data.extend(tlv(0x73, &key));
+            data.extend(extension);
+            unlock(authenticator);
+            assert_eq!(
+                send(authenticator, &apdu(0x01, 0x00, 0x00, &data)),
+                Err(Status::IncorrectDataParameter)
+            );
+        }
+
+        // With the period of 60 seconds and T0 of 1000, as used by the host
+        register(authenticator, b"reverse", 0x41, 6, SECRET);
+        let time_step = (1_700_000_000 - 1000) / 60;
+        verify_code(authenticator, totp(time_step), time_step).unwrap();
+    });
+}
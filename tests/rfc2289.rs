@@ -0,0 +1,163 @@
+mod common;
+
+use common::{apdu, select, send, tlv, unlock, with_authenticator, VirtClient};
+use iso7816::Status;
+use oath_authenticator::Authenticator;
+
+/// Pass phrase and seed from RFC 2289, Appendix C
+const PASSPHRASE: &[u8] = b"This is a test.";
+const SEED: &[u8] = b"TeSt";
+
+/// Registers the S/KEY credential with the given (kind | algorithm) byte and sequence number
+fn register_hash_chain(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    kind_algorithm: u8,
+    sequence: u16,
+) {
+    let mut key = vec![kind_algorithm, 0];
+    key.extend_from_slice(PASSPHRASE);
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x73, &key));
+    data.extend(tlv(0x93, SEED));
+    data.extend(tlv(0x94, &sequence.to_be_bytes()));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+/// Returns the sequence number and the OTP, in the hexadecimal or six-word form
+fn next_otp(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    hex: bool,
+) -> (u16, Vec<u8>) {
+    unlock(authenticator);
+    let response = send(
+        authenticator,
+        &apdu(0xb9, 0x00, hex as u8, &tlv(0x71, label)),
+    )
+    .unwrap();
+    assert_eq!(&response[..2], &[0x94, 0x02]);
+    let sequence = u16::from_be_bytes(response[2..4].try_into().unwrap());
+    assert_eq!(response[4], 0x95);
+    (sequence, response[6..6 + response[5] as usize].to_vec())
+}
+
+#[test]
+fn md5_test_vectors() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_hash_chain(authenticator, b"skey-md5", 0x64, 99);
+        assert_eq!(
+            next_otp(authenticator, b"skey-md5", true),
+            (99, b"50FE1962C4965880".to_vec())
+        );
+        // The sequence number is decremented on each use
+        assert_eq!(next_otp(authenticator, b"skey-md5", true).0, 98);
+
+        register_hash_chain(authenticator, b"skey-md5", 0x64, 1);
+        assert_eq!(
+            next_otp(authenticator, b"skey-md5", true),
+            (1, b"7965E05436F5029F".to_vec())
+        );
+        // Refused once the sequence number reaches zero
+        unlock(authenticator);
+        assert!(send(
+            authenticator,
+            &apdu(0xb9, 0x00, 0x01, &tlv(0x71, b"skey-md5"))
+        )
+        .is_err());
+    });
+}
+
+#[test]
+fn sha1_test_vectors() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_hash_chain(authenticator, b"skey-sha1", 0x61, 99);
+        assert_eq!(
+            next_otp(authenticator, b"skey-sha1", true),
+            (99, b"87FEC7768B73CCF9".to_vec())
+        );
+
+        register_hash_chain(authenticator, b"skey-sha1", 0x61, 1);
+        assert_eq!(
+            next_otp(authenticator, b"skey-sha1", true),
+            (1, b"63D936639734385B".to_vec())
+        );
+    });
+}
+
+/// Six-word forms from RFC 2289, Appendix C, with the (kind | algorithm) byte and the sequence number
+const SIX_WORD_VECTORS: [(u8, u16, &str); 4] = [
+    (0x64, 1, "EASE OIL FUM CURE AWRY AVIS"),
+    (0x64, 99, "BAIL TUFT BITS GANG CHEF THY"),
+    (0x61, 1, "CART OTTO HIVE ODE VAT NUT"),
+    (0x61, 99, "GAFF WAIT SKID GIG SKY EYED"),
+];
+
+/// Words of the RFC 2289 dictionary with up to three letters, sorted before the four-letter ones
+const SHORT_WORDS_COUNT: u16 = 571;
+
+#[test]
+fn six_word_indices() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut words = Vec::new();
+        for (kind_algorithm, sequence, six_words) in SIX_WORD_VECTORS {
+            register_hash_chain(authenticator, b"skey", kind_algorithm, sequence);
+            let (_, indices) = next_otp(authenticator, b"skey", false);
+            assert_eq!(indices.len(), 12);
+            let indices = indices
+                .chunks(2)
+                .map(|index| u16::from_be_bytes(index.try_into().unwrap()));
+            words.extend(six_words.split(' ').zip(indices));
+        }
+
+        // Without the dictionary at hand, check the indices against its layout: the short
+        // words come first, and both parts are sorted alphabetically
+        for (word, index) in &words {
+            assert_eq!(word.len() <= 3, *index < SHORT_WORDS_COUNT, "{}", word);
+        }
+        for (word, index) in &words {
+            for (other_word, other_index) in &words {
+                if (word.len() <= 3) == (other_word.len() <= 3) {
+                    assert_eq!(
+                        word.cmp(other_word),
+                        index.cmp(other_index),
+                        "{} {}",
+                        word,
+                        other_word
+                    );
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn expected_sequence_number() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_hash_chain(authenticator, b"skey", 0x64, 99);
+
+        let calculate = |sequence: u16| {
+            let mut data = tlv(0x71, b"skey");
+            data.extend(tlv(0x94, &sequence.to_be_bytes()));
+            apdu(0xb9, 0x00, 0x01, &data)
+        };
+        // The server challenge is for a different sequence number
+        unlock(authenticator);
+        assert_eq!(
+            send(authenticator, &calculate(98)),
+            Err(Status::IncorrectDataParameter)
+        );
+
+        // The sequence number is not used up by the refused request
+        unlock(authenticator);
+        let response = send(authenticator, &calculate(99)).unwrap();
+        assert_eq!(&response[..4], &[0x94, 0x02, 0x00, 99]);
+        assert_eq!(&response[6..], b"50FE1962C4965880");
+        assert_eq!(next_otp(authenticator, b"skey", true).0, 98);
+    });
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{
+    apdu, calculate_code, register, select, send, tlv, unlock, with_authenticator, VirtClient,
+};
+use oath_authenticator::Authenticator;
+
+/// Seed for HMAC-SHA512 from RFC 6238, Appendix B - 64 bytes
+const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+/// (Unix time, expected TOTP) pairs from RFC 6238, Appendix B, for the HMAC-SHA512 mode
+const VECTORS_SHA512: [(u64, u32); 6] = [
+    (59, 90693936),
+    (1111111109, 25091201),
+    (1111111111, 99943326),
+    (1234567890, 93441116),
+    (2000000000, 38618901),
+    (20000000000, 47863826),
+];
+
+#[test]
+fn totp_sha512_test_vectors() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // Kind::Totp | Algorithm::Sha512, 8 digits
+        register(authenticator, b"rfc6238-sha512", 0x23, 8, SEED_SHA512);
+
+        for (time, expected) in VECTORS_SHA512 {
+            let code = calculate_code(authenticator, b"rfc6238-sha512", time / 30);
+            assert_eq!(code, expected, "T = {}", time);
+        }
+    });
+}
+
+/// Calculates the code with the Unix time sent as the challenge (P1 = 0x01)
+fn calculate_code_for_unix_time(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    unix_time: u64,
+) -> u32 {
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x74, &unix_time.to_be_bytes()));
+    unlock(authenticator);
+    let response = send(authenticator, &apdu(0xa2, 0x01, 0x01, &data)).unwrap();
+    assert_eq!(&response[..2], &[0x76, 0x05]);
+    let digits = response[2] as u32;
+    let truncated = u32::from_be_bytes(response[3..7].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 10u32.pow(digits)
+}
+
+#[test]
+fn totp_sha512_unix_time() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register(authenticator, b"rfc6238-sha512", 0x23, 8, SEED_SHA512);
+
+        for (time, expected) in VECTORS_SHA512 {
+            let code = calculate_code_for_unix_time(authenticator, b"rfc6238-sha512", time);
+            assert_eq!(code, expected, "T = {}", time);
+        }
+    });
+}
+
+#[test]
+fn totp_custom_period_and_t0() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut key = vec![0x23, 8];
+        key.extend_from_slice(SEED_SHA512);
+        let mut data = tlv(0x71, b"period");
+        data.extend(tlv(0x73, &key));
+        data.extend(tlv(0x8a, &60u32.to_be_bytes()));
+        data.extend(tlv(0x8b, &1000u64.to_be_bytes()));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        for (time, _) in VECTORS_SHA512 {
+            let time = time + 1000;
+            assert_eq!(
+                calculate_code_for_unix_time(authenticator, b"period", time),
+                calculate_code(authenticator, b"period", (time - 1000) / 60),
+                "T = {}",
+                time
+            );
+        }
+    });
+}
+
+#[test]
+fn totp_steam_guard_format() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let mut key = vec![0x21, 5];
+        key.extend_from_slice(b"12345678901234567890");
+        let mut data = tlv(0x71, b"steam");
+        data.extend(tlv(0x73, &key));
+        data.extend(tlv(0x91, &[0x01]));
+        unlock(authenticator);
+        send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+
+        // The code string is returned as is, for T = 59
+        let mut data = tlv(0x71, b"steam");
+        data.extend(tlv(0x74, &1u64.to_be_bytes()));
+        unlock(authenticator);
+        let response = send(authenticator, &apdu(0xa2, 0x00, 0x01, &data)).unwrap();
+        assert_eq!(&response[..], b"\x92\x06\x05PV9M4");
+    });
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{apdu, select, send, tlv, unlock, with_authenticator, VirtClient};
+use oath_authenticator::Authenticator;
+
+/// Seeds from RFC 6287, Appendix C
+const SEED: &[u8] = b"12345678901234567890";
+const SEED32: &[u8] = b"12345678901234567890123456789012";
+const SEED64: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+/// SHA1 of the PIN "1234"
+const PIN_HASH: [u8; 20] = hex_literal::hex!("7110eda4d09e062aa5e4a390b0a572ac0d2c0220");
+
+/// Registers the OCRA credential with the given suite. The header has to agree with it.
+fn register_ocra(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    algorithm: u8,
+    digits: u8,
+    secret: &[u8],
+    suite: &[u8],
+) {
+    let mut key = vec![0x50 | algorithm, digits];
+    key.extend_from_slice(secret);
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x73, &key));
+    data.extend(tlv(0x8d, suite));
+    unlock(authenticator);
+    send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).unwrap();
+}
+
+/// Calculates the truncated OCRA response, and decodes it to the final code.
+fn calculate_ocra(
+    authenticator: &mut Authenticator<VirtClient>,
+    label: &[u8],
+    question: &[u8],
+    pin_hash: Option<&[u8]>,
+    unix_time: Option<u64>,
+) -> u32 {
+    let mut data = tlv(0x71, label);
+    data.extend(tlv(0x74, question));
+    if let Some(pin_hash) = pin_hash {
+        data.extend(tlv(0x8e, pin_hash));
+    }
+    if let Some(unix_time) = unix_time {
+        data.extend(tlv(0x90, &unix_time.to_be_bytes()));
+    }
+    unlock(authenticator);
+    let response = send(authenticator, &apdu(0xb8, 0x00, 0x01, &data)).unwrap();
+    assert_eq!(&response[..2], &[0x76, 0x05]);
+    let digits = response[2] as u32;
+    let truncated = u32::from_be_bytes(response[3..7].try_into().unwrap());
+    (truncated & 0x7FFF_FFFF) % 10u32.pow(digits)
+}
+
+fn repeated(digit: u8) -> Vec<u8> {
+    vec![b'0' + digit; 8]
+}
+
+#[test]
+fn one_way_challenge_response_sha1() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_ocra(
+            authenticator,
+            b"ocra",
+            0x01,
+            6,
+            SEED,
+            b"OCRA-1:HOTP-SHA1-6:QN08",
+        );
+
+        let expected = [
+            237653, 243178, 653583, 740991, 608993, 388898, 816933, 224598, 750600, 294470,
+        ];
+        for (i, expected) in expected.into_iter().enumerate() {
+            let question = repeated(i as u8);
+            let code = calculate_ocra(authenticator, b"ocra", &question, None, None);
+            assert_eq!(code, expected, "Q = {:?}", question);
+        }
+    });
+}
+
+#[test]
+fn counter_and_pin_sha256() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let suite = b"OCRA-1:HOTP-SHA256-8:C-QN08-PSHA1";
+        register_ocra(authenticator, b"ocra", 0x02, 8, SEED32, suite);
+
+        // The counter starts at zero, and is bumped on each calculation
+        let expected = [
+            65347737, 86775851, 78192410, 71565254, 10104329, 65983500, 70069104, 91771096,
+            75011558, 8522129,
+        ];
+        for (counter, expected) in expected.into_iter().enumerate() {
+            let code = calculate_ocra(authenticator, b"ocra", b"12345678", Some(&PIN_HASH), None);
+            assert_eq!(code, expected, "C = {}", counter);
+        }
+
+        // The PIN hash is required by the suite
+        let mut data = tlv(0x71, b"ocra");
+        data.extend(tlv(0x74, b"12345678"));
+        unlock(authenticator);
+        assert!(send(authenticator, &apdu(0xb8, 0x00, 0x01, &data)).is_err());
+    });
+}
+
+#[test]
+fn pin_sha256() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let suite = b"OCRA-1:HOTP-SHA256-8:QN08-PSHA1";
+        register_ocra(authenticator, b"ocra", 0x02, 8, SEED32, suite);
+
+        let expected = [83238735, 1501458, 17957585, 86776967, 86807031];
+        for (i, expected) in expected.into_iter().enumerate() {
+            let question = repeated(i as u8);
+            let code = calculate_ocra(authenticator, b"ocra", &question, Some(&PIN_HASH), None);
+            assert_eq!(code, expected, "Q = {:?}", question);
+        }
+    });
+}
+
+#[test]
+fn counter_sha512() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        register_ocra(
+            authenticator,
+            b"ocra",
+            0x03,
+            8,
+            SEED64,
+            b"OCRA-1:HOTP-SHA512-8:C-QN08",
+        );
+
+        let expected = [
+            7016083, 63947962, 70123924, 25341727, 33203315, 34205738, 44343969, 51946085,
+            20403879, 31409299,
+        ];
+        for (i, expected) in expected.into_iter().enumerate() {
+            let question = repeated(i as u8);
+            let code = calculate_ocra(authenticator, b"ocra", &question, None, None);
+            assert_eq!(code, expected, "C = {}", i);
+        }
+    });
+}
+
+#[test]
+fn timestamp_sha512() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        let suite = b"OCRA-1:HOTP-SHA512-8:QN08-T1M";
+        register_ocra(authenticator, b"ocra", 0x03, 8, SEED64, suite);
+
+        // T = 0x132d0b6 minutes
+        let unix_time = 0x132d0b6 * 60 + 59;
+        let expected = [95209754, 55907591, 22048402, 24218844, 36209546];
+        for (i, expected) in expected.into_iter().enumerate() {
+            let question = repeated(i as u8);
+            let code = calculate_ocra(authenticator, b"ocra", &question, None, Some(unix_time));
+            assert_eq!(code, expected, "Q = {:?}", question);
+        }
+    });
+}
+
+#[test]
+fn suite_mismatch_is_rejected() {
+    with_authenticator(|authenticator| {
+        select(authenticator);
+        // The header declares SHA1, while the suite uses SHA256
+        let mut key = vec![0x51, 8];
+        key.extend_from_slice(SEED32);
+        let mut data = tlv(0x71, b"ocra");
+        data.extend(tlv(0x73, &key));
+        data.extend(tlv(0x8d, b"OCRA-1:HOTP-SHA256-8:QN08"));
+        unlock(authenticator);
+        assert!(send(authenticator, &apdu(0x01, 0x00, 0x00, &data)).is_err());
+    });
+}